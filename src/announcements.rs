@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use serenity::all::{ChannelId, Colour, CreateEmbed, CreateMessage, GuildChannel, UserId};
+use serenity::prelude::*;
+
+use crate::data::UserData;
+
+///Key in a user's storage that turns their rank up announcements off when set to `OPTED_OUT`
+pub const OPT_OUT_KEY: &str = "levelup_announcements";
+pub const OPTED_OUT: &str = "off";
+///What gets said when neither the guild nor the rank file says otherwise
+pub const DEFAULT_TEMPLATE: &str = "GG {user}, you just advanced to **{rank}** !";
+///Longest template a guild can set, leaving room for the placeholders to grow within Discord's limit
+pub const MAX_TEMPLATE_LENGTH: usize = 1500;
+
+///Where rank ups get announced
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnounceTarget {
+    ///The channel the message that ranked them up was sent in
+    #[default]
+    Same,
+    ///Always the same channel
+    Channel(u64),
+    ///A direct message to whoever ranked up
+    Dm,
+    ///Nowhere, ranks change quietly
+    None
+}
+impl AnnounceTarget {
+    pub fn describe(&self) -> String {
+        return match self {
+            AnnounceTarget::Same => "in the channel they ranked up in".to_string(),
+            AnnounceTarget::Channel(channel_id) => format!("in <#{}>", channel_id),
+            AnnounceTarget::Dm => "in a DM".to_string(),
+            AnnounceTarget::None => "nowhere".to_string()
+        };
+    }
+}
+
+///How a guild announces rank ups
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct AnnouncementSettings {
+    pub target: AnnounceTarget,
+    ///Text with {user}, {rank}, {level} and {xp} placeholders, `DEFAULT_TEMPLATE` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    ///Send the text in an embed colored like the rank instead of as a plain message
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub embed: bool
+}
+impl AnnouncementSettings {
+    pub fn is_default(&self) -> bool {
+        return *self == AnnouncementSettings::default();
+    }
+
+    ///The text for a rank up. A rank's own announcement from the rank file wins over the guild's template.
+    pub fn text(&self, rank_announcement: Option<String>, user: &UserData, rank: &str, level: u64) -> String {
+        let template = rank_announcement.or(self.template.clone()).unwrap_or(DEFAULT_TEMPLATE.to_string());
+        return fill(&template, user.id, rank, level, user.xp);
+    }
+
+    ///Sends a rank up announcement wherever the guild wants it, whoever opted out has already been left out
+    pub async fn announce(&self, ctx: &Context, channel: &GuildChannel, user_id: u64, text: String, color: (u8, u8, u8)) {
+        let message = if self.embed {
+            let (r, g, b) = color;
+            CreateMessage::new().embed(CreateEmbed::new().description(text).colour(Colour::from_rgb(r, g, b)))
+        } else {
+            CreateMessage::new().content(text)
+        };
+        let result = match self.target {
+            AnnounceTarget::Same => channel.send_message(&ctx.http, message).await.map(|_| ()),
+            AnnounceTarget::Channel(channel_id) => ChannelId::new(channel_id).send_message(&ctx.http, message).await.map(|_| ()),
+            AnnounceTarget::Dm => UserId::new(user_id).direct_message(ctx, message).await.map(|_| ()),
+            AnnounceTarget::None => Ok(())
+        };
+        if let Err(why) = result {
+            println!("Error sending rank up announcement: {why:?}");
+        }
+    }
+}
+
+///Whether a user turned announcements of their rank ups off
+pub fn opted_out(user: &UserData) -> bool {
+    return user.user_data.get(OPT_OUT_KEY).is_some_and(|value| value == OPTED_OUT);
+}
+
+///Fills in a template's placeholders
+pub fn fill(template: &str, user_id: u64, rank: &str, level: u64, xp: u64) -> String {
+    return template.replace("{user}", &format!("<@{}>", user_id)).replace("{rank}", rank).replace("{level}", &level.to_string()).replace("{xp}", &xp.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::Timestamp;
+
+    #[test]
+    fn fills_every_placeholder() {
+        assert_eq!(fill("{user} hit {rank} (level {level}, {xp} xp)", 5, "Elder", 3, 120), "<@5> hit Elder (level 3, 120 xp)");
+    }
+
+    #[test]
+    fn rank_file_text_beats_the_guild_template() {
+        let mut user = UserData::new(5, "five".to_string(), Timestamp::from_unix_timestamp(0).unwrap());
+        user.xp = 20;
+        let default = AnnouncementSettings::default();
+        assert_eq!(default.text(None, &user, "Regular", 2), "GG <@5>, you just advanced to **Regular** !");
+        let custom = AnnouncementSettings { template: Some("{user} is level {level}".to_string()), ..Default::default() };
+        assert_eq!(custom.text(None, &user, "Regular", 2), "<@5> is level 2");
+        assert_eq!(custom.text(Some("{user} is a regular with {xp} xp".to_string()), &user, "Regular", 2), "<@5> is a regular with 20 xp");
+    }
+
+    #[test]
+    fn saves_targets_readably() {
+        let settings = AnnouncementSettings { target: AnnounceTarget::Channel(9), embed: true, ..Default::default() };
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(json, r#"{"target":{"channel":9},"embed":true}"#);
+        assert_eq!(serde_json::from_str::<AnnouncementSettings>(&json).unwrap(), settings);
+        assert_eq!(serde_json::from_str::<AnnouncementSettings>(r#"{"target":"dm"}"#).unwrap().target, AnnounceTarget::Dm);
+    }
+}
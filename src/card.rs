@@ -0,0 +1,275 @@
+///Width of the rank card in pixels
+pub const CARD_WIDTH: u32 = 600;
+///Height of the rank card in pixels
+pub const CARD_HEIGHT: u32 = 180;
+
+const BACKGROUND: (u8, u8, u8) = (0x23, 0x27, 0x2a);
+const PANEL: (u8, u8, u8) = (0x2c, 0x2f, 0x33);
+const TRACK: (u8, u8, u8) = (0x48, 0x4b, 0x51);
+const TEXT: (u8, u8, u8) = (0xf2, 0xf3, 0xf5);
+const MUTED: (u8, u8, u8) = (0xb9, 0xbb, 0xbe);
+
+///Everything shown on a rank card, kept apart from Discord types so cards can be rendered in tests
+#[derive(Clone, Debug, PartialEq)]
+pub struct RankCard {
+    pub name: String,
+    pub level: u64,
+    pub rank: String,
+    pub xp: u64,
+    ///Where the current level started
+    pub start: u64,
+    ///Where the next level starts, `None` at the top rank
+    pub next: Option<u64>,
+    pub position: Option<usize>,
+    pub color: (u8, u8, u8)
+}
+impl RankCard {
+    ///Draws the card
+    pub fn render(&self) -> Canvas {
+        let mut canvas = Canvas::new(CARD_WIDTH, CARD_HEIGHT, BACKGROUND);
+        canvas.fill_rect(8, 8, CARD_WIDTH - 16, CARD_HEIGHT - 16, PANEL);
+
+        // Avatar placeholder, a circle in the rank color with the first letter of the name
+        canvas.fill_circle(90, 90, 60, self.color);
+        let initial: String = self.name.chars().next().map(|c| c.to_string()).unwrap_or("?".to_string());
+        canvas.draw_text(90 - text_width(&initial, 6) as i32 / 2, 90 - 21, &initial, 6, PANEL);
+
+        let left = 170;
+        let right = CARD_WIDTH as i32 - 24;
+        canvas.draw_text(left, 26, &fit(&self.name, 4, (right - left) as u32), 4, TEXT);
+        let rank = if self.rank.is_empty() {"Unranked".to_string()} else {self.rank.clone()};
+        canvas.draw_text(left, 66, &fit(&rank, 2, (right - left) as u32), 2, self.color);
+        let mut stats = format!("LEVEL {}", self.level);
+        if let Some(position) = self.position {
+            stats += &format!("  #{}", position);
+        }
+        canvas.draw_text(left, 92, &stats, 2, MUTED);
+
+        let bar_width = (right - left) as u32;
+        canvas.fill_rect(left as u32, 118, bar_width, 20, TRACK);
+        let (done, total) = match self.next {
+            Some(next) => (self.xp.saturating_sub(self.start), next.saturating_sub(self.start)),
+            None => (1, 1)
+        };
+        let filled = if total == 0 {bar_width} else {((done.min(total) as u128 * bar_width as u128) / total as u128) as u32};
+        canvas.fill_rect(left as u32, 118, filled, 20, self.color);
+
+        let progress = match self.next {
+            Some(next) => format!("{} / {} XP", self.xp, next),
+            None => format!("{} XP, TOP RANK", self.xp)
+        };
+        canvas.draw_text(right - text_width(&progress, 2) as i32, 146, &progress, 2, MUTED);
+        return canvas;
+    }
+}
+
+///An RGBA image being drawn on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>
+}
+impl Canvas {
+    pub fn new(width: u32, height: u32, fill: (u8, u8, u8)) -> Canvas {
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..width * height {
+            pixels.extend_from_slice(&[fill.0, fill.1, fill.2, 0xff]);
+        }
+        return Canvas { width, height, pixels };
+    }
+
+    ///Sets a pixel, anything outside the canvas is ignored
+    pub fn set(&mut self, x: i32, y: i32, color: (u8, u8, u8)) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        let index = ((y as u32 * self.width + x as u32) * 4) as usize;
+        self.pixels[index..index + 4].copy_from_slice(&[color.0, color.1, color.2, 0xff]);
+    }
+
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: (u8, u8, u8)) {
+        for py in y..y + height {
+            for px in x..x + width {
+                self.set(px as i32, py as i32, color);
+            }
+        }
+    }
+
+    pub fn fill_circle(&mut self, cx: i32, cy: i32, radius: i32, color: (u8, u8, u8)) {
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                if x * x + y * y <= radius * radius {
+                    self.set(cx + x, cy + y, color);
+                }
+            }
+        }
+    }
+
+    ///Draws text in the built-in 5x7 font, each font pixel becoming a `scale` sized square
+    pub fn draw_text(&mut self, x: i32, y: i32, text: &str, scale: u32, color: (u8, u8, u8)) {
+        let scale = scale as i32;
+        for (index, c) in text.chars().enumerate() {
+            let origin = x + index as i32 * 6 * scale;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..5 {
+                    if bits & (0b10000 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            self.set(origin + column * scale + dx, y + row as i32 * scale + dy, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn encode_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut bytes = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.pixels)?;
+        }
+        return Ok(bytes);
+    }
+}
+
+///How wide text is in pixels at a scale, including the gap after each character except the last
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    let count = text.chars().count() as u32;
+    return if count == 0 {0} else {(count * 6 - 1) * scale};
+}
+
+///Shortens text with `..` until it fits in `width` pixels
+fn fit(text: &str, scale: u32, width: u32) -> String {
+    if text_width(text, scale) <= width {
+        return text.to_string();
+    }
+    let mut fitted: String = text.to_string();
+    while !fitted.is_empty() && text_width(&format!("{}..", fitted), scale) > width {
+        fitted.pop();
+    }
+    return format!("{}..", fitted.trim_end());
+}
+
+///The 5x7 bitmap for a character, one byte per row with the leftmost pixel in bit 4.
+///Letters are drawn as capitals and anything without a glyph becomes a hollow box.
+fn glyph(c: char) -> [u8; 7] {
+    return match c.to_ascii_uppercase() {
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        ' ' => [0; 7],
+        '-' => [0, 0, 0, 0b11111, 0, 0, 0],
+        '_' => [0, 0, 0, 0, 0, 0, 0b11111],
+        '.' => [0, 0, 0, 0, 0, 0b01100, 0b01100],
+        ',' => [0, 0, 0, 0, 0b01100, 0b00100, 0b01000],
+        ':' => [0, 0b01100, 0b01100, 0, 0b01100, 0b01100, 0],
+        '/' => [0b00001, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b10000],
+        '#' => [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010],
+        '!' => [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0, 0b00100],
+        '?' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0, 0b00100],
+        '\'' => [0b00100, 0b00100, 0b01000, 0, 0, 0, 0],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        '+' => [0, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0],
+        '&' => [0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101],
+        _ => [0b11111, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11111]
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, io, path::PathBuf};
+
+    ///Reads back an RGBA PNG, like one written by `encode_png`
+    fn decode_png(bytes: &[u8]) -> io::Result<Canvas> {
+        let decoder = png::Decoder::new(bytes);
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).map_err(io::Error::other)?;
+        if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+            return Err(io::Error::other("not an 8 bit RGBA image"));
+        }
+        pixels.truncate(info.buffer_size());
+        return Ok(Canvas { width: info.width, height: info.height, pixels });
+    }
+
+    ///Compares a card against `src/fixtures/<name>.png`. Run with UPDATE_GOLDEN=1 to rewrite the fixture after an intended change.
+    fn assert_golden(card: &RankCard, name: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/fixtures").join(format!("{}.png", name));
+        let rendered = card.render();
+        if env::var("UPDATE_GOLDEN").is_ok() {
+            fs::write(&path, rendered.encode_png().unwrap()).unwrap();
+        }
+        let golden = decode_png(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!((rendered.width, rendered.height), (golden.width, golden.height));
+        let differing = rendered.pixels.chunks(4).zip(golden.pixels.chunks(4)).filter(|(a, b)| a != b).count();
+        assert_eq!(differing, 0, "{} pixels differ from {}", differing, path.display());
+    }
+
+    #[test]
+    fn renders_a_card_partway_through_a_level() {
+        assert_golden(&RankCard { name: "Luna".to_string(), level: 2, rank: "Novice - Rank II".to_string(), xp: 120, start: 50, next: Some(200), position: Some(3), color: (0x85, 0x99, 0x00) }, "rank_card_progress");
+    }
+
+    #[test]
+    fn renders_a_card_at_the_top_rank() {
+        assert_golden(&RankCard { name: "Someone With A Very Long Name".to_string(), level: 21, rank: "Eternal - Rank XXI".to_string(), xp: 140000, start: 130000, next: None, position: None, color: (0xb5, 0x89, 0x00) }, "rank_card_top");
+    }
+
+    #[test]
+    fn png_round_trips() {
+        let mut canvas = Canvas::new(3, 2, (1, 2, 3));
+        canvas.set(2, 1, (200, 100, 50));
+        canvas.set(9, 9, (0, 0, 0));
+        assert_eq!(decode_png(&canvas.encode_png().unwrap()).unwrap(), canvas);
+    }
+
+    #[test]
+    fn long_text_is_shortened_to_fit() {
+        assert_eq!(fit("short", 2, 1000), "short");
+        let fitted = fit("Someone With A Very Long Name", 4, 406);
+        assert!(fitted.ends_with(".."));
+        assert!(text_width(&fitted, 4) <= 406);
+    }
+}
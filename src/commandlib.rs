@@ -1,226 +1,176 @@
-use std::collections::HashMap;
 use std::sync::Arc;
 
-use serenity::all::{ChannelId, CreateEmbed, CreateEmbedFooter, CreateMessage, GuildId, Member, PartialGuild, Role, UserId};
-use serenity::model::channel::Message;
+use serenity::all::{GuildId, Http, Member, PartialGuild, Permissions, RoleId, UserId};
+use serenity::http::{HttpError, StatusCode};
 
 use serenity::prelude::*;
 
 use crate::data::*;
+use crate::error::CommandError;
+use crate::framework::Args;
+use crate::invocation::*;
+use crate::ranks::*;
+use crate::tokenizer::DEFAULT_PREFIX;
+
+///Most members Discord hands out per request
+const MEMBER_PAGE: u64 = 1000;
+///Why listing members was refused, Discord answers 403 when the bot lacks the Server Members intent
+const MEMBERS_INTENT_MISSING: &str = "Discord won't list this server's members, turn on the Server Members intent for the bot in the Discord developer portal";
 
 //////////////////////////////////////////
 // Functions to get data to pass around //
 //////////////////////////////////////////
 
-///Gets the data for a specific user
-pub async fn get_user_data(ctx: Context, user_id: u64) -> UserData {
-    return get_user_data_lock(ctx).await.read().await.data[&user_id].clone();
+///Gets the data for a specific user in a guild, if they've been recorded there
+pub async fn get_user_data(ctx: Context, guild_id: u64, user_id: u64) -> Option<UserData> {
+    return get_user_data_lock(ctx).await.read().await.guild(guild_id).and_then(|ledger| ledger.users.get(&user_id)).cloned();
 }
 
-///Gets the data for a specific user by name (I'm not happy with this yet)
-pub async fn get_user_data_from_name(ctx: Context, name: String) -> Option<UserData> {
-    for user in get_user_data_lock(ctx).await.read().await.data.values() {
-        if user.name.to_uppercase() == name.to_uppercase() {
-            return Some(user.to_owned());
-        }
-    }
-
-    return None;
+///Gets the data for a specific user in a guild by name, allowing partial names
+pub async fn get_user_data_from_name(ctx: Context, guild_id: u64, name: String) -> Option<UserData> {
+    return get_user_data_lock(ctx).await.read().await.guild(guild_id).and_then(|ledger| ledger.find_user_by_name(&name)).cloned();
 }
 
-///Lets you store arbitrary data as a string on a user. Returns whether it was successful.
-pub async fn set_user_storage(ctx: Context, user_id: u64, key: String, value: String) -> bool {
-    if let Some(data) = get_user_data_lock(ctx).await.write().await.data.get_mut(&user_id) {
-        data.user_data.insert(key, value);
-        return true;
+///Works out whose record a command is about from a `Member` argument, the author when it's left out
+pub async fn get_target_user_data(ctx: Context, guild_id: u64, cmd: &Invocation, args: &Args, name: &str) -> Result<UserData, CommandError> {
+    if let Some(text) = args.text(name) {
+        return get_user_data_from_name(ctx, guild_id, text.clone()).await.ok_or(CommandError::UnknownName(text));
     }
-    return false;
+    let user_id = args.user(name).unwrap_or(cmd.author().id);
+    return get_user_data(ctx, guild_id, user_id.into()).await.ok_or(CommandError::NoRecord { user_id, author: user_id == cmd.author().id });
 }
 
-///Lets you grab a string from stored data on a user
-pub async fn get_user_storage(ctx: Context, user_id: u64, key: String) -> Option<String> {
-    return get_user_data_lock(ctx).await.read().await.data[&user_id].user_data.get(&key).cloned();
+///Lets you store arbitrary data as a string on a user in a guild. Errors when the user hasn't been recorded there.
+pub async fn set_user_storage(ctx: Context, guild_id: u64, user_id: u64, key: String, value: String) -> Result<(), CommandError> {
+    let data_lock = get_user_data_lock(ctx).await;
+    let mut data = data_lock.write().await;
+    let Some(user) = data.guilds.get_mut(&guild_id).and_then(|ledger| ledger.users.get_mut(&user_id)) else {
+        return Err(CommandError::NoRecord { user_id: UserId::new(user_id), author: false });
+    };
+    user.user_data.insert(key, value);
+    data.mark_user_changed(guild_id, user_id);
+    return Ok(());
 }
 
-///Gets the data for a server, or "Guild"
-pub async fn get_guild(ctx: Context, msg: Message) -> Option<PartialGuild>{
-    if let Some(guild_id) = msg.guild_id {
-        if let Ok(guild) = ctx.http.get_guild(guild_id).await {
-            return Some(guild);
-        }
-    }
-    return None;
+///Lets you grab a string from stored data on a user in a guild. Errors when the user hasn't been recorded there,
+///and is `Ok(None)` when they have but nothing's stored under the key.
+pub async fn get_user_storage(ctx: Context, guild_id: u64, user_id: u64, key: String) -> Result<Option<String>, CommandError> {
+    let Some(user) = get_user_data(ctx, guild_id, user_id).await else {
+        return Err(CommandError::NoRecord { user_id: UserId::new(user_id), author: false });
+    };
+    return Ok(user.user_data.get(&key).cloned());
 }
 
 //////////////////////
 // Functions to use //
 //////////////////////
 
-///Says a message in the channel. Returns whether the message got sent.
-pub async fn say(ctx: Context, channel_id: ChannelId, message: String) -> bool {
-    if let Err(why) = channel_id.say(&ctx.http, message).await {
-        println!("Error sending message: {why:?}");
-        return false;
-    }
-    return true;
+///Answers a command with a message, works for both `!` and slash commands. Returns whether the message got sent.
+pub async fn reply(ctx: Context, cmd: &Invocation, message: String) -> bool {
+    return cmd.reply(&ctx, message).await;
 }
 
-///Sends an embed in the channel. Returns whether the message got sent.
-pub async fn embed(ctx: Context, channel_id: ChannelId, title: String, message: String, footer: Option<String>) -> bool{
-    let mut embed = CreateEmbed::new().title(title).description(message);
-    if let Some(footer_text) = footer {
-        embed = embed.footer(CreateEmbedFooter::new(footer_text));
+///Works out a member's server wide permissions from their roles
+pub fn member_permissions(guild: &PartialGuild, member: &Member) -> Permissions {
+    if guild.owner_id == member.user.id {
+        return Permissions::all();
     }
-    let builder = CreateMessage::new().embed(embed);
-    if let Err(why) = channel_id.send_message(&ctx.http, builder).await {
-        println!("Error sending message: {why:?}");
-        return false;
+    let mut permissions = guild.roles.get(&RoleId::new(guild.id.get())).map(|everyone| everyone.permissions).unwrap_or_default();
+    for role_id in &member.roles {
+        if let Some(role) = guild.roles.get(role_id) {
+            permissions |= role.permissions;
+        }
     }
-    return true;
-}
-
-///Gets the nickname of a user
-pub async fn get_nickname(ctx: Context, user: UserId, guild_id: GuildId) -> Option<String>{
-    if let Ok(user_data) = user.to_user(ctx.http.clone()).await {
-        return user_data.nick_in(ctx.http, guild_id).await;
+    if permissions.administrator() {
+        return Permissions::all();
     }
-    return None;
+    return permissions;
 }
 
-///Gets a rank's name for a level
-pub fn rank(level: u64) -> String {
-    let thresholds = vec![
-        ("", 0),
-        ("Beginner - Rank I", 1),
-        ("Novice - Rank II", 2),
-        ("Expert - Rank III", 3),
-        ("Master - Rank IV", 4),
-        ("Sage - Rank V", 5),
-        ("Legend - Rank VI", 6),
-        ("Myth - Rank VII", 7),
-        ("Lord - Rank VIII", 8),
-        ("Wizard - Rank IX", 9),
-        ("Ghost - Rank X", 10),
-        ("Demon - Rank XI", 11),
-        ("No Life - Rank XII", 12),
-        ("Grinder - Rank XIII", 13),
-        ("Farmer - Rank XIV", 14),
-        ("Destroyer - Rank XV", 15),
-        ("Obliterator - Rank XVI", 16),
-        ("Millionaire - Rank XVII", 17),
-        ("Billionaire - Rank XVIII", 18),
-        ("Ascendant - Rank XIX", 19),
-        ("Overflowing - Rank XX", 20),
-        ("Eternal - Rank XXI", 21)
-    ];
-
-    let mut ret_val = "";
-
-    for (name, threshold) in thresholds {
-        if level >= threshold {
-            ret_val = name;
+///Checks whether whoever ran a command has the given permissions in the server
+pub async fn has_permission(ctx: Context, cmd: &Invocation, required: Permissions) -> bool {
+    if let Invocation::Slash(command) = cmd {
+        if let Some(permissions) = command.member.as_ref().and_then(|member| member.permissions) {
+            return permissions.contains(required);
+        }
+    }
+    if let Some(guild_id) = cmd.guild_id() {
+        if let Ok(guild) = ctx.http.get_guild(guild_id).await {
+            if let Ok(member) = ctx.http.get_member(guild_id, cmd.author().id).await {
+                return member_permissions(&guild, &member).contains(required);
+            }
         }
     }
+    return false;
+}
 
-    return ret_val.to_string();
-}
-
-
-pub fn level_ansi_color(level: u64) -> String {
-    match level {
-        1 => "36",
-        2 => "32",
-        3 => "42;32",
-        4 => "40;33",
-        5 => "41;37",
-        6 => "40;31",
-        7 => "47;32",
-        8 => "40;34",
-        9 => "45;37",
-        10 => "42;30",
-        11 => "41;30",
-        12 => "47;36",
-        13 => "42;35",
-        14 => "45;30",
-        15 => "40;32",
-        16 => "47;35",
-        17 => "42;33",
-        18 => "42;34",
-        19 => "42;37",
-        20 => "40;35",
-        21 => "47;33",
-        _ => "37"
-    }.to_string()
-}
-
-pub fn get_level_thresholds() -> Vec<u64>{
-    return vec![
-        10,
-        50,
-        200,
-        500,
-        1000,
-        2000,
-        3250,
-        5000,
-        7000,
-        10000,
-        13500,
-        17500,
-        22000,
-        27500,
-        35000,
-        45000,
-        57500,
-        70000,
-        85000,
-        105000,
-        130000
-    ];
+///The HTTP status Discord answered a failed request with, if it got that far
+pub fn discord_status(why: &serenity::Error) -> Option<StatusCode> {
+    return match why {
+        serenity::Error::Http(why) => why.status_code(),
+        _ => None
+    };
 }
 
-///Gets a level from an amount of xp
-pub fn level(xp: u64) -> u64 {
-    let thresholds = get_level_thresholds();
+///Discord's error code for a member who isn't in the server, a 404 can also mean the role or guild is gone
+pub const UNKNOWN_MEMBER: isize = 10007;
 
-    let mut ret_val = 0;
+///The error code in Discord's answer to a failed request, more specific than the HTTP status
+pub fn discord_code(why: &serenity::Error) -> Option<isize> {
+    return match why {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => Some(response.error.code),
+        _ => None
+    };
+}
 
-    for threshold in thresholds {
-        if xp >= threshold {
-            ret_val += 1;
+///Gets every member of a guild, a page at a time. Discord only lists members to bots with the privileged
+///Server Members intent turned on in the developer portal, without it this fails with an error saying so.
+pub async fn get_all_members(http: &Http, guild_id: GuildId) -> Result<Vec<Member>, CommandError> {
+    let mut members = vec![];
+    let mut after: Option<UserId> = None;
+    loop {
+        let page = match http.get_guild_members(guild_id, Some(MEMBER_PAGE), after.map(u64::from)).await {
+            Ok(page) => page,
+            Err(why) if discord_status(&why) == Some(StatusCode::FORBIDDEN) => return Err(CommandError::Invalid(MEMBERS_INTENT_MISSING.to_string())),
+            Err(why) => return Err(why.into())
+        };
+        let full = page.len() as u64 == MEMBER_PAGE;
+        after = page.last().map(|member| member.user.id);
+        members.extend(page);
+        if !full {
+            return Ok(members);
         }
     }
+}
 
-    return ret_val;
+///Gets the rank table a guild uses
+pub async fn get_rank_table(ctx: Context, guild_id: u64) -> Arc<RankTable> {
+    let data_read = ctx.data.read().await;
+    return data_read.get::<RankData>().expect("Expected RankData in TypeMap.").for_guild(guild_id);
 }
 
-///Returns the string needed to ping the user
-pub async fn ping(user_id: UserId) -> String {
-    return format!("<@{}>", u64::from(user_id));
+///Gets the command prefix for a guild, or the default one outside guilds
+pub async fn get_prefix(ctx: Context, guild_id: Option<u64>) -> String {
+    let Some(guild_id) = guild_id else {
+        return DEFAULT_PREFIX.to_string();
+    };
+    let data_lock = get_user_data_lock(ctx).await;
+    let data = data_lock.read().await;
+    return data.guild(guild_id).and_then(|ledger| ledger.prefix.clone()).unwrap_or(DEFAULT_PREFIX.to_string());
 }
 
-///Adds a role to a member (returns if it was successful)
-pub async fn add_role(ctx: Context, member: Member, guild_id: GuildId, role_name: &str) -> bool {
-    if let Ok(guild) = ctx.http.get_guild(guild_id).await {
-        if let Some(role) = get_role(guild, role_name).await {
-            if let Ok(_) = member.add_role(ctx.clone().http, role.id).await {
-                return true;
-            }
-        }
-    }
-    return false;
+///Gets a rank's name for a level
+pub fn rank(ranks: &RankTable, level: u64) -> String {
+    return ranks.rank(level);
 }
 
-///Removes a role from a member (returns if it was successful)
-pub async fn remove_role(ctx: Context, member: Member, guild_id: GuildId, role_name: &str) -> bool {
-    if let Ok(guild) = ctx.http.get_guild(guild_id).await {
-        if let Some(role) = get_role(guild, role_name).await {
-            if let Ok(_) = member.remove_role(ctx.clone().http, role.id).await {
-                return true;
-            }
-        }
-    }
-    return false;
+pub fn get_level_thresholds(ranks: &RankTable) -> Vec<u64>{
+    return ranks.thresholds();
+}
+
+///Gets a level from an amount of xp
+pub fn level(ranks: &RankTable, xp: u64) -> u64 {
+    return ranks.level(xp);
 }
 
 //////////////////////////////////////////////////////////
@@ -235,11 +185,3 @@ pub async fn get_user_data_lock(ctx: Context) -> Arc<serenity::prelude::RwLock<D
     };
     return data_lock;
 }
-
-///Gets the data for a role
-pub async fn get_role(guild: PartialGuild, role_name: &str) -> Option<Role> {
-    if let Some(role) = guild.role_by_name(role_name) {
-        return Some(role.to_owned());
-    }
-    return None;
-}
\ No newline at end of file
@@ -0,0 +1,148 @@
+use serenity::all::Permissions;
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::announcements::*;
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+
+///Reads where announcements should go, `--channel` wins over a word
+fn parse_target(word: Option<String>, channel_id: Option<u64>) -> Result<Option<AnnounceTarget>, CommandError> {
+    if let Some(channel_id) = channel_id {
+        return Ok(Some(AnnounceTarget::Channel(channel_id)));
+    }
+    let Some(word) = word else {
+        return Ok(None);
+    };
+    return match word.to_lowercase().as_str() {
+        "same" | "here" => Ok(Some(AnnounceTarget::Same)),
+        "dm" | "dms" => Ok(Some(AnnounceTarget::Dm)),
+        "none" | "off" => Ok(Some(AnnounceTarget::None)),
+        _ => Err(CommandError::Invalid(format!("\"{}\" isn't somewhere to announce, try same, dm, none or --channel", word)))
+    };
+}
+
+///The current settings and what an announcement looks like with them
+fn describe(settings: &AnnouncementSettings) -> String {
+    let template = settings.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+    let form = if settings.embed {"an embed"} else {"a message"};
+    return format!("Rank ups are announced {} as {}, like this:\n> {}\nTemplates can use {{user}}, {{rank}}, {{level}} and {{xp}}, ranks with their own announcement in the rank file use that instead.", settings.target.describe(), form, template);
+}
+
+pub struct Announcements;
+#[async_trait]
+impl Command for Announcements {
+    fn name(&self) -> &'static str {
+        return "announcements";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Chooses where and how rank ups get announced, or shows the current setup";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("target", ArgKind::Text, "same, dm or none"),
+            ArgSpec::optional("channel", ArgKind::Channel, "Announce in this channel instead").named(),
+            ArgSpec::optional("template", ArgKind::Text, "What to say, with {user}, {rank}, {level} and {xp}, or default").named(),
+            ArgSpec::optional("embed", ArgKind::Flag, "Announce in an embed colored like the rank"),
+            ArgSpec::optional("plain", ArgKind::Flag, "Announce as a plain message")
+        ];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let target = parse_target(args.text("target"), args.channel("channel").map(u64::from))?;
+        let template = args.text("template");
+        if template.as_ref().is_some_and(|template| template.chars().count() > MAX_TEMPLATE_LENGTH) {
+            return Err(CommandError::Invalid(format!("Templates can be up to {} characters long", MAX_TEMPLATE_LENGTH)));
+        }
+        if args.flag("embed") && args.flag("plain") {
+            return Err(CommandError::Invalid("Pick one of --embed and --plain".to_string()));
+        }
+        let data_lock = get_user_data_lock(ctx.clone()).await;
+        let settings = {
+            let mut data = data_lock.write().await;
+            let settings = &mut data.guild_mut(guild_id.into()).announcements;
+            let before = settings.clone();
+            if let Some(target) = target {
+                settings.target = target;
+            }
+            if let Some(template) = template {
+                settings.template = if template.eq_ignore_ascii_case("default") {None} else {Some(template)};
+            }
+            if args.flag("embed") || args.flag("plain") {
+                settings.embed = args.flag("embed");
+            }
+            let settings = settings.clone();
+            if settings != before {
+                data.mark_guild_changed(guild_id.into());
+            }
+            settings
+        };
+        reply(ctx, &cmd, describe(&settings)).await;
+        return Ok(());
+    }
+}
+
+pub struct LevelUps;
+#[async_trait]
+impl Command for LevelUps {
+    fn name(&self) -> &'static str {
+        return "levelups";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Turns announcements of your own rank ups on or off";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("setting", ArgKind::Text, "on or off")];
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let user_id = cmd.author().id;
+        // Anyone who hasn't been recorded yet has nothing to announce, so this is about themselves either way
+        let no_record = |_| CommandError::NoRecord { user_id, author: true };
+        let opted_out = match args.text("setting").map(|setting| setting.to_lowercase()).as_deref() {
+            None => get_user_storage(ctx.clone(), guild_id.into(), user_id.into(), OPT_OUT_KEY.to_string()).await.map_err(no_record)?.as_deref() == Some(OPTED_OUT),
+            Some("on") => {
+                set_user_storage(ctx.clone(), guild_id.into(), user_id.into(), OPT_OUT_KEY.to_string(), "on".to_string()).await.map_err(no_record)?;
+                false
+            },
+            Some("off") => {
+                set_user_storage(ctx.clone(), guild_id.into(), user_id.into(), OPT_OUT_KEY.to_string(), OPTED_OUT.to_string()).await.map_err(no_record)?;
+                true
+            },
+            Some(setting) => return Err(CommandError::Invalid(format!("\"{}\" isn't on or off", setting)))
+        };
+        let out = if opted_out {"Your rank ups won't be announced"} else {"Your rank ups will be announced"};
+        reply(ctx, &cmd, out.to_string()).await;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_targets() {
+        assert_eq!(parse_target(None, None).unwrap(), None);
+        assert_eq!(parse_target(Some("DM".to_string()), None).unwrap(), Some(AnnounceTarget::Dm));
+        assert_eq!(parse_target(Some("off".to_string()), None).unwrap(), Some(AnnounceTarget::None));
+        assert_eq!(parse_target(Some("same".to_string()), Some(4)).unwrap(), Some(AnnounceTarget::Channel(4)));
+        assert!(parse_target(Some("everywhere".to_string()), None).is_err());
+    }
+}
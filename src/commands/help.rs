@@ -0,0 +1,26 @@
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::commands::all_commands;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+
+pub struct Help;
+#[async_trait]
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        return "help";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Prints this page";
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, _args: Args) -> CommandResult {
+        let prefix = get_prefix(ctx.clone(), cmd.guild_id().map(u64::from)).await;
+        reply(ctx, &cmd, all_commands().help(&prefix)).await;
+        return Ok(());
+    }
+}
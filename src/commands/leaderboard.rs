@@ -0,0 +1,289 @@
+use serenity::all::{ButtonStyle, ComponentInteraction, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter, CreateInteractionResponse, CreateInteractionResponseMessage, Timestamp};
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::data::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+use crate::ranks::RankTable;
+
+///Rows on one page of the leaderboard
+const PAGE_SIZE: usize = 20;
+///Start of the custom id on the previous/next buttons
+const BUTTON_PREFIX: &str = "leaderboard";
+
+///What the leaderboard is ordered by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sort {
+    Xp,
+    Messages,
+    Weekly,
+    Monthly,
+    ///The running season when it has a known start, since XP resets then, otherwise the last `DAILY_XP_DAYS` days
+    Seasonal
+}
+impl Sort {
+    ///Every sort's name, as offered to `!leaderboard` and the slash command
+    const NAMES: [&'static str; 5] = ["xp", "messages", "weekly", "monthly", "seasonal"];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Sort::Xp => "xp",
+            Sort::Messages => "messages",
+            Sort::Weekly => "weekly",
+            Sort::Monthly => "monthly",
+            Sort::Seasonal => "seasonal"
+        };
+    }
+
+    pub fn parse(name: &str) -> Option<Sort> {
+        return match name.to_lowercase().as_str() {
+            "xp" => Some(Sort::Xp),
+            "messages" => Some(Sort::Messages),
+            "weekly" => Some(Sort::Weekly),
+            "monthly" => Some(Sort::Monthly),
+            "seasonal" => Some(Sort::Seasonal),
+            _ => None
+        };
+    }
+
+    fn title(&self, in_season: bool) -> String {
+        return match self {
+            Sort::Xp => "XP LEADERBOARD".to_string(),
+            Sort::Messages => "MESSAGE LEADERBOARD".to_string(),
+            Sort::Weekly => "WEEKLY XP LEADERBOARD".to_string(),
+            Sort::Monthly => "MONTHLY XP LEADERBOARD".to_string(),
+            Sort::Seasonal if in_season => "SEASONAL XP LEADERBOARD (THIS SEASON)".to_string(),
+            Sort::Seasonal => format!("SEASONAL XP LEADERBOARD ({} DAYS)", DAILY_XP_DAYS)
+        };
+    }
+
+    fn unit(&self) -> &'static str {
+        return if *self == Sort::Messages {"messages"} else {"xp"};
+    }
+
+    ///A user's score, windows end on `today`. `in_season` is whether the running season's start is known.
+    fn score(&self, user: &UserData, today: i64, in_season: bool) -> u64 {
+        return match self {
+            Sort::Xp => user.xp,
+            Sort::Messages => user.messages,
+            Sort::Weekly => user.xp_in_last_days(7, today),
+            Sort::Monthly => user.xp_in_last_days(30, today),
+            // Starting a season resets XP, and per-day XP doesn't reach back far enough for long seasons
+            Sort::Seasonal if in_season => user.xp,
+            Sort::Seasonal => user.xp_in_last_days(DAILY_XP_DAYS, today)
+        };
+    }
+
+    ///Windowed boards leave out everyone who scored nothing in the window
+    fn windowed(&self) -> bool {
+        return matches!(self, Sort::Weekly | Sort::Monthly | Sort::Seasonal);
+    }
+}
+
+///Which page to show
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Page {
+    Number(usize),
+    ///The page the viewer is on
+    Me
+}
+
+///Reads the sort and page out of the arguments, the first page of the XP board when neither is given
+pub fn read_view(args: &Args) -> (Sort, Page) {
+    let sort = args.text("sort").and_then(|sort| Sort::parse(&sort)).unwrap_or(Sort::Xp);
+    if args.flag("me") {
+        return (sort, Page::Me);
+    }
+    return (sort, Page::Number(args.integer("page").unwrap_or(1).max(1) as usize));
+}
+
+///How many pages a board of `len` rows has, an empty board still has one
+pub fn page_count(len: usize) -> usize {
+    return len.div_ceil(PAGE_SIZE).max(1);
+}
+
+///Works out the page to show, clamped to the pages that exist. `Me` falls back to the first page
+///when the viewer isn't on the board.
+pub fn resolve_page(board: &[(&UserData, u64)], page: Page, viewer: u64) -> usize {
+    let number = match page {
+        Page::Number(number) => number,
+        Page::Me => board.iter().position(|(user, _)| user.id == viewer).map(|index| index / PAGE_SIZE + 1).unwrap_or(1)
+    };
+    return number.clamp(1, page_count(board.len()));
+}
+
+///The rows of one page as an ANSI block, with the viewer's row marked
+pub fn render_rows(board: &[(&UserData, u64)], page: usize, viewer: u64, ranks: &RankTable, sort: Sort) -> String {
+    if board.is_empty() {
+        return "Nobody's on this board yet".to_string();
+    }
+    let mut rows = String::new();
+    for (index, (user, score)) in board.iter().enumerate().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+        let (marker, style) = if user.id == viewer {("▶", "1;4")} else {(" ", "1")};
+        rows += &format!("{}\u{001b}[{};{}m#{} {}: {} {}\u{001b}[0m\n", marker, style, ranks.color(user.level), index + 1, user.name, score, sort.unit());
+    }
+    return format!("```ansi\n{}```", rows);
+}
+
+///Builds a page of the leaderboard with its buttons
+pub fn leaderboard_page(ledger: Option<&GuildData>, ranks: &RankTable, sort: Sort, page: Page, viewer: u64, now: Timestamp) -> (CreateEmbed, Vec<CreateActionRow>) {
+    let today = day_number(now);
+    let in_season = ledger.is_some_and(|ledger| ledger.season_started.is_some());
+    let board: Vec<(&UserData, u64)> = ledger.map(|ledger| ledger.ranked_by(|user| sort.score(user, today, in_season))).unwrap_or_default()
+        .into_iter().filter(|(_, score)| !sort.windowed() || *score > 0).collect();
+    let pages = page_count(board.len());
+    let number = resolve_page(&board, page, viewer);
+
+    let mut footer = format!("Page {} of {}", number, pages);
+    if page == Page::Me && !board.iter().any(|(user, _)| user.id == viewer) {
+        footer += ", you're not on this board yet";
+    }
+    let embed = CreateEmbed::new().title(sort.title(in_season)).description(render_rows(&board, number, viewer, ranks, sort)).footer(CreateEmbedFooter::new(footer));
+    return (embed, vec![CreateActionRow::Buttons(page_buttons(sort, number, pages, viewer))]);
+}
+
+///The previous and next buttons. Each id ends in its direction, on a single page both buttons go to
+///page 1 and Discord refuses two components with the same id.
+fn page_buttons(sort: Sort, number: usize, pages: usize, viewer: u64) -> Vec<CreateButton> {
+    let button = |label: &str, direction: &str, target: usize, disabled: bool| {
+        return CreateButton::new(format!("{}:{}:{}:{}:{}", BUTTON_PREFIX, sort.name(), target, viewer, direction)).label(label).style(ButtonStyle::Secondary).disabled(disabled);
+    };
+    return vec![button("◀ Previous", "prev", number.saturating_sub(1).max(1), number <= 1), button("Next ▶", "next", (number + 1).min(pages), number >= pages)];
+}
+
+///Turns the page when someone clicks previous or next. Returns whether the button belonged to the leaderboard.
+pub async fn handle_button(ctx: Context, interaction: &ComponentInteraction) -> bool {
+    let parts: Vec<&str> = interaction.data.custom_id.split(':').collect();
+    let [BUTTON_PREFIX, sort, page, viewer, "prev" | "next"] = parts.as_slice() else {
+        return false;
+    };
+    let (Some(sort), Ok(page), Ok(viewer), Some(guild_id)) = (Sort::parse(sort), page.parse(), viewer.parse(), interaction.guild_id) else {
+        return true;
+    };
+    let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+    let (embed, components) = {
+        let data_lock = get_user_data_lock(ctx.clone()).await;
+        let data = data_lock.read().await;
+        leaderboard_page(data.guild(guild_id.into()), &ranks, sort, Page::Number(page), viewer, Timestamp::now())
+    };
+    let response = CreateInteractionResponse::UpdateMessage(CreateInteractionResponseMessage::new().embed(embed).components(components));
+    if let Err(why) = interaction.create_response(&ctx.http, response).await {
+        println!("Error turning leaderboard page: {why:?}");
+    }
+    return true;
+}
+
+pub struct Leaderboard;
+#[async_trait]
+impl Command for Leaderboard {
+    fn name(&self) -> &'static str {
+        return "leaderboard";
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        return &["lb"];
+    }
+
+    fn description(&self) -> &'static str {
+        return "Shows the top users by xp, messages, or weekly, monthly or seasonal xp, me jumps to your page";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("sort", ArgKind::Choice(&Sort::NAMES), "What to rank by, xp when left out"),
+            ArgSpec::optional("page", ArgKind::Integer, "Which page to show"),
+            ArgSpec::optional("me", ArgKind::Flag, "Jump to the page you're on").bare()
+        ];
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let (sort, page) = read_view(&args);
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        let (embed, components) = {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let data = data_lock.read().await;
+            leaderboard_page(data.guild(guild_id.into()), &ranks, sort, page, cmd.author().id.into(), cmd.timestamp())
+        };
+
+        cmd.reply_embed_with_components(&ctx, embed, components).await;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    fn users(count: u64) -> Vec<UserData> {
+        return (1..=count).map(|id| {
+            let mut user = UserData::new(id, format!("user {}", id), Timestamp::from_unix_timestamp(0).unwrap());
+            user.xp = 1000 - id;
+            user
+        }).collect();
+    }
+
+    #[test]
+    fn reads_sort_page_and_me() {
+        let view = |line: &str| parse_args(&Leaderboard.args(), &tokenize(line).unwrap()).map(|args| read_view(&args));
+        assert_eq!(view("").unwrap(), (Sort::Xp, Page::Number(1)));
+        assert_eq!(view("weekly 3").unwrap(), (Sort::Weekly, Page::Number(3)));
+        assert_eq!(view("me Messages").unwrap(), (Sort::Messages, Page::Me));
+        assert_eq!(view("seasonal").unwrap(), (Sort::Seasonal, Page::Number(1)));
+        assert_eq!(view("-5").unwrap(), (Sort::Xp, Page::Number(1)));
+        assert!(view("sideways").is_err());
+    }
+
+    #[test]
+    fn pages_never_come_out_empty() {
+        assert_eq!(page_count(0), 1);
+        assert_eq!(page_count(20), 1);
+        assert_eq!(page_count(21), 2);
+        let users = users(40);
+        let board: Vec<(&UserData, u64)> = users.iter().map(|user| (user, user.xp)).collect();
+        // 40 rows is exactly two pages, the old `len/20 + 1` clamp allowed an empty third
+        assert_eq!(resolve_page(&board, Page::Number(3), 0), 2);
+        assert_eq!(resolve_page(&board, Page::Number(0), 0), 1);
+        assert_eq!(resolve_page(&[], Page::Number(5), 0), 1);
+    }
+
+    #[test]
+    fn seasonal_board_follows_the_running_season() {
+        let today = day_number(Timestamp::now());
+        let mut user = UserData::new(1, "one".to_string(), Timestamp::from_unix_timestamp(0).unwrap());
+        user.add_xp(30, Timestamp::from_unix_timestamp((today - DAILY_XP_DAYS - 10) * 86400).unwrap());
+        user.add_xp(5, Timestamp::now());
+        assert_eq!(Sort::Seasonal.score(&user, today, false), 5);
+        assert_eq!(Sort::Seasonal.score(&user, today, true), 35);
+        assert_eq!(Sort::Seasonal.title(false), format!("SEASONAL XP LEADERBOARD ({} DAYS)", DAILY_XP_DAYS));
+    }
+
+    #[test]
+    fn page_buttons_never_share_an_id() {
+        let ids = |number, pages| page_buttons(Sort::Weekly, number, pages, 9).iter()
+            .map(|button| serde_json::to_value(button).unwrap()["custom_id"].as_str().unwrap().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(ids(1, 1), vec!["leaderboard:weekly:1:9:prev", "leaderboard:weekly:1:9:next"]);
+        assert_eq!(ids(2, 3), vec!["leaderboard:weekly:1:9:prev", "leaderboard:weekly:3:9:next"]);
+    }
+
+    #[test]
+    fn me_finds_the_viewers_page() {
+        let users = users(45);
+        let board: Vec<(&UserData, u64)> = users.iter().map(|user| (user, user.xp)).collect();
+        assert_eq!(resolve_page(&board, Page::Me, 20), 1);
+        assert_eq!(resolve_page(&board, Page::Me, 21), 2);
+        assert_eq!(resolve_page(&board, Page::Me, 45), 3);
+        assert_eq!(resolve_page(&board, Page::Me, 99), 1);
+        let rows = render_rows(&board, 2, 21, &RankTable::default(), Sort::Xp);
+        assert!(rows.contains("▶\u{001b}[1;4;37m#21 user 21: 979 xp"));
+        assert!(rows.contains(" \u{001b}[1;37m#22 user 22"));
+        assert!(!rows.contains("#20 "));
+    }
+}
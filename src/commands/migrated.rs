@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+
+use serenity::all::Permissions;
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+
+pub struct ClaimMigrated;
+#[async_trait]
+impl Command for ClaimMigrated {
+    fn name(&self) -> &'static str {
+        return "claimmigrated";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Moves members recorded before the bot kept servers apart onto this server's leaderboard";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("all", ArgKind::Flag, "Take every waiting record, not only this server's members")];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::ADMINISTRATOR;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let data_lock = get_user_data_lock(ctx.clone()).await;
+        if data_lock.read().await.unassigned.is_empty() {
+            return Err(CommandError::Invalid("There are no records waiting for a server".to_string()));
+        }
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        cmd.reply_after(&ctx, "Moving waiting records onto this server's leaderboard...".to_string(), async {
+            let (moved, left) = if args.flag("all") {
+                let mut data = data_lock.write().await;
+                let moved = data.unassigned.len();
+                data.assign_unassigned(guild_id.into(), &ranks);
+                (moved, 0)
+            } else {
+                let members: HashSet<u64> = get_all_members(&ctx.http, guild_id).await?.into_iter().map(|member| u64::from(member.user.id)).collect();
+                let mut data = data_lock.write().await;
+                let moved = data.assign_unassigned_members(guild_id.into(), &members, &ranks);
+                (moved, data.unassigned.len())
+            };
+            let mut out = format!("Moved {} records onto this server's leaderboard.", moved);
+            if left > 0 {
+                out += &format!(" {} records of people who aren't in this server are still waiting.", left);
+            }
+            return Ok(out);
+        }).await;
+        return Ok(());
+    }
+}
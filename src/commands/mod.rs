@@ -0,0 +1,54 @@
+use serenity::all::ComponentInteraction;
+use serenity::prelude::*;
+
+use crate::framework::*;
+
+mod announcements;
+mod help;
+mod leaderboard;
+mod migrated;
+mod prefix;
+mod profile;
+mod purgebots;
+mod rankroles;
+mod rank;
+mod seasons;
+mod weeklytop;
+mod xp;
+mod xppolicy;
+
+///Every command the bot knows, in the order help lists them
+pub fn all_commands() -> Registry {
+    return Registry::new(vec![
+        Box::new(help::Help),
+        Box::new(leaderboard::Leaderboard),
+        Box::new(xp::Xp),
+        Box::new(xp::XpCooldown),
+        Box::new(profile::Profile),
+        Box::new(rank::Rank),
+        Box::new(prefix::Prefix),
+        Box::new(migrated::ClaimMigrated),
+        Box::new(xppolicy::Policy),
+        Box::new(xppolicy::ChannelMultiplier),
+        Box::new(xppolicy::RoleMultiplier),
+        Box::new(xppolicy::NoXp),
+        Box::new(purgebots::PurgeBots),
+        Box::new(rankroles::RankRolesCommand),
+        Box::new(rankroles::RoleModeCommand),
+        Box::new(rankroles::SyncRoles),
+        Box::new(announcements::Announcements),
+        Box::new(announcements::LevelUps),
+        Box::new(weeklytop::WeeklyTop),
+        Box::new(seasons::Seasons),
+        Box::new(seasons::EndSeason),
+        Box::new(seasons::StartSeason)
+    ]);
+}
+
+///Handles a click on a button one of the commands sent
+pub async fn dispatch_component(ctx: Context, interaction: ComponentInteraction) {
+    if leaderboard::handle_button(ctx, &interaction).await {
+        return;
+    }
+    println!("unknown component: {}", interaction.data.custom_id);
+}
@@ -0,0 +1,50 @@
+use serenity::all::Permissions;
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+use crate::tokenizer::DEFAULT_PREFIX;
+
+///Longest prefix a guild can set, anything longer is more likely a mistake than a choice
+const MAX_PREFIX_LENGTH: usize = 5;
+
+pub struct Prefix;
+#[async_trait]
+impl Command for Prefix {
+    fn name(&self) -> &'static str {
+        return "prefix";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Changes what commands start with in this server";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::required("prefix", ArgKind::Text, "The new prefix, like ! or ?")];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let prefix = args.text("prefix").unwrap_or_default();
+        if prefix.is_empty() || prefix.chars().count() > MAX_PREFIX_LENGTH || prefix.chars().any(char::is_whitespace) {
+            return Err(CommandError::Invalid(format!("The prefix has to be 1 to {} characters without spaces", MAX_PREFIX_LENGTH)));
+        }
+        {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let mut data = data_lock.write().await;
+            data.guild_mut(guild_id.into()).prefix = if prefix == DEFAULT_PREFIX {None} else {Some(prefix.clone())};
+            data.mark_guild_changed(guild_id.into());
+        }
+        reply(ctx, &cmd, format!("Commands now start with `{}`, like `{}help`", prefix, prefix)).await;
+        return Ok(());
+    }
+}
@@ -0,0 +1,91 @@
+use serenity::all::{Colour, CreateEmbed, CreateEmbedFooter, UserId};
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+
+///How many cells the progress bar has
+const BAR_WIDTH: usize = 16;
+
+pub struct Profile;
+#[async_trait]
+impl Command for Profile {
+    fn name(&self) -> &'static str {
+        return "profile";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Shows your or someone else's rank, level, XP and progress to the next level";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("user", ArgKind::Member, "Whose profile to show, by mention, id or name")];
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        let user = get_target_user_data(ctx.clone(), guild_id.into(), &cmd, &args, "user").await?;
+        let (position, total) = {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let data = data_lock.read().await;
+            let ledger = data.guild(guild_id.into());
+            (ledger.and_then(|ledger| ledger.position(user.id)), ledger.map(|ledger| ledger.users.len()).unwrap_or(0))
+        };
+
+        let level = ranks.level(user.xp);
+        let rank_name = if level == 0 {"Unranked".to_string()} else {rank(&ranks, level)};
+        let (start, next) = ranks.progress(user.xp);
+        let progress = match next {
+            Some(next) => format!("`{}` {}/{} XP, {} to go", progress_bar(user.xp - start, next - start, BAR_WIDTH), user.xp, next, next - user.xp),
+            None => format!("`{}` Top rank reached", progress_bar(1, 1, BAR_WIDTH))
+        };
+        let (red, green, blue) = ranks.rgb(level);
+
+        let mut embed = CreateEmbed::new()
+            .title(format!("{}'s profile", user.name))
+            .colour(Colour::from_rgb(red, green, blue))
+            .field("Rank", rank_name, true)
+            .field("Level", level.to_string(), true)
+            .field("XP", user.xp.to_string(), true)
+            .field("Progress", progress, false)
+            .field("Leaderboard", position.map(|position| format!("#{} of {}", position, total)).unwrap_or("Unranked".to_string()), true)
+            .field("Messages", user.messages.to_string(), true)
+            .field("On the ledger since", user.first_seen.map(|joined| format!("<t:{}:D>", joined.unix_timestamp())).unwrap_or("Before tracking began".to_string()), true);
+        let user_id = UserId::new(user.id);
+        let avatar = if user_id == cmd.author().id {Some(cmd.author().face())} else {ctx.http.get_user(user_id).await.ok().map(|member| member.face())};
+        if let Some(avatar) = avatar {
+            embed = embed.thumbnail(avatar);
+        }
+        embed = embed.footer(CreateEmbedFooter::new(format!("ID {}", user.id)));
+
+        cmd.reply_embed(&ctx, embed).await;
+        return Ok(());
+    }
+}
+
+///Draws how far `done` is through `total` as a bar of `width` cells
+pub fn progress_bar(done: u64, total: u64, width: usize) -> String {
+    let filled = if total == 0 {width} else {((done.min(total) as u128 * width as u128) / total as u128) as usize};
+    return format!("{}{}", "█".repeat(filled), "░".repeat(width - filled));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_bar_fills_proportionally() {
+        assert_eq!(progress_bar(0, 10, 4), "░░░░");
+        assert_eq!(progress_bar(5, 10, 4), "██░░");
+        assert_eq!(progress_bar(9, 10, 4), "███░");
+        assert_eq!(progress_bar(10, 10, 4), "████");
+        assert_eq!(progress_bar(15, 10, 4), "████");
+        assert_eq!(progress_bar(0, 0, 4), "████");
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashSet;
+
+use serenity::all::{GuildId, Permissions, UserId};
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+
+///Most removed bots named in the reply
+const LISTED: usize = 20;
+///Most people who left that get looked up on Discord, each one is a request of its own
+const MAX_LOOKUPS: usize = 100;
+
+///Which of the recorded ids belong to bots, and how many people who left couldn't be checked
+struct Found {
+    bots: HashSet<u64>,
+    unchecked: usize
+}
+
+///Works out which of `ids` belong to bots. Current members come from the member list a page at a time,
+///anyone who left comes from the cache or is looked up on their own, up to `MAX_LOOKUPS` of them.
+async fn find_bots(ctx: &Context, guild_id: GuildId, ids: &[u64]) -> Result<Found, CommandError> {
+    let mut found = Found { bots: HashSet::new(), unchecked: 0 };
+    let mut seen = HashSet::new();
+    for member in get_all_members(&ctx.http, guild_id).await? {
+        seen.insert(u64::from(member.user.id));
+        if member.user.bot {
+            found.bots.insert(u64::from(member.user.id));
+        }
+    }
+    let mut lookups = 0;
+    for id in ids.iter().filter(|id| !seen.contains(id)) {
+        if let Some(bot) = ctx.cache.user(UserId::new(*id)).map(|user| user.bot) {
+            if bot {
+                found.bots.insert(*id);
+            }
+            continue;
+        }
+        if lookups == MAX_LOOKUPS {
+            found.unchecked += 1;
+            continue;
+        }
+        lookups += 1;
+        match ctx.http.get_user(UserId::new(*id)).await {
+            Ok(user) if user.bot => {
+                found.bots.insert(*id);
+            },
+            Ok(_) => {},
+            Err(why) => println!("Error looking up {} for purgebots: {why:?}", id)
+        }
+    }
+    return Ok(found);
+}
+
+///Takes the bots off a guild's leaderboard, returning their names
+async fn remove_bots(ctx: &Context, guild_id: GuildId, bots: &HashSet<u64>) -> Vec<String> {
+    let data_lock = get_user_data_lock(ctx.clone()).await;
+    let mut data = data_lock.write().await;
+    let ledger = data.guild_mut(guild_id.into());
+    let mut removed = vec![];
+    ledger.users.retain(|id, user| {
+        if bots.contains(id) {
+            removed.push(user.name.clone());
+            return false;
+        }
+        return true;
+    });
+    if !removed.is_empty() {
+        data.mark_ledger_changed(guild_id.into());
+    }
+    return removed;
+}
+
+///What the follow up says once the bots are gone
+fn report(removed: &[String], unchecked: usize) -> String {
+    let mut out = if removed.is_empty() {"No bots were on the leaderboard".to_string()} else {format!("Removed {} bots: {}", removed.len(), removed.iter().take(LISTED).cloned().collect::<Vec<String>>().join(", "))};
+    if removed.len() > LISTED {
+        out += &format!(" and {} more", removed.len() - LISTED);
+    }
+    if unchecked > 0 {
+        out += &format!(". {} recorded members who left weren't checked, there were too many to look up", unchecked);
+    }
+    return out;
+}
+
+pub struct PurgeBots;
+#[async_trait]
+impl Command for PurgeBots {
+    fn name(&self) -> &'static str {
+        return "purgebots";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Removes bots that were recorded before bots stopped earning XP";
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, _args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let data_lock = get_user_data_lock(ctx.clone()).await;
+        let ids: Vec<u64> = {
+            let data = data_lock.read().await;
+            data.guild(guild_id.into()).map(|ledger| ledger.users.keys().copied().collect()).unwrap_or_default()
+        };
+        cmd.reply_after(&ctx, format!("Checking {} recorded members for bots...", ids.len()), async {
+            let found = find_bots(&ctx, guild_id, &ids).await?;
+            return Ok(report(&remove_bots(&ctx, guild_id, &found.bots).await, found.unchecked));
+        }).await;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_what_was_removed_and_left_unchecked() {
+        assert_eq!(report(&[], 0), "No bots were on the leaderboard");
+        let names: Vec<String> = (1..=22).map(|n| format!("bot{}", n)).collect();
+        let out = report(&names, 3);
+        assert!(out.starts_with("Removed 22 bots: bot1, bot2"));
+        assert!(out.contains("bot20 and 2 more. 3 recorded members who left weren't checked"));
+    }
+}
@@ -0,0 +1,46 @@
+use serenity::all::CreateAttachment;
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::card::RankCard;
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+
+pub struct Rank;
+#[async_trait]
+impl Command for Rank {
+    fn name(&self) -> &'static str {
+        return "rank";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Shows your or someone else's rank card";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("user", ArgKind::Member, "Whose rank card to show, by mention, id or name")];
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        let user = get_target_user_data(ctx.clone(), guild_id.into(), &cmd, &args, "user").await?;
+        let position = {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let data = data_lock.read().await;
+            data.guild(guild_id.into()).and_then(|ledger| ledger.position(user.id))
+        };
+
+        let level = level(&ranks, user.xp);
+        let (start, next) = ranks.progress(user.xp);
+        let card = RankCard { name: user.name.clone(), level, rank: rank(&ranks, level), xp: user.xp, start, next, position, color: ranks.rgb(level) };
+        let png = card.render().encode_png().map_err(|why| CommandError::Invalid(format!("Couldn't draw the rank card: {}", why)))?;
+
+        cmd.reply_file(&ctx, CreateAttachment::bytes(png, "rank.png")).await;
+        return Ok(());
+    }
+}
@@ -0,0 +1,224 @@
+use serenity::all::{Colour, EditRole, GuildId, Permissions};
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+use crate::ranks::RankTable;
+use crate::reconcile::reconcile_guild;
+use crate::roles::{RankRoles, RoleMode, RoleSettings};
+
+///One line per rank saying which role it gives and whether that role is bound or only found by name
+fn describe(ranks: &RankTable, settings: &RoleSettings, rank_roles: &RankRoles) -> String {
+    let mut out = format!("Role mode: **{}**, {}\n", settings.mode.name(), settings.mode.description());
+    for level in 1..=ranks.ranks.len() as u64 {
+        let role = match rank_roles.role(level) {
+            Some(role_id) if settings.bound.get(&level) == Some(&u64::from(role_id)) => format!("<@&{}>", role_id),
+            Some(role_id) => format!("<@&{}> (by name, not bound)", role_id),
+            None => format!("no role, nothing is called \"{}\"", ranks.role(level))
+        };
+        out += &format!("Level {} **{}**: {}", level, ranks.rank(level), role);
+        if let Some(role_id) = rank_roles.cosmetic.get(&level) {
+            out += &format!(", cosmetic <@&{}>", role_id);
+        }
+        out += "\n";
+    }
+    return out;
+}
+
+///Binds every rank to a role, creating roles for ranks that don't have one yet. Returns how many were created.
+async fn create_missing(ctx: &Context, guild_id: GuildId, ranks: &RankTable, settings: &mut RoleSettings) -> Result<usize, CommandError> {
+    let rank_roles = RankRoles::load(ctx, guild_id, settings, ranks).await;
+    let mut created = 0;
+    for level in 1..=ranks.ranks.len() as u64 {
+        let role_id = match rank_roles.role(level) {
+            Some(role_id) => role_id,
+            None => {
+                let (r, g, b) = ranks.rgb(level);
+                let role = guild_id.create_role(&ctx.http, EditRole::new().name(ranks.role(level)).colour(Colour::from_rgb(r, g, b)).audit_log_reason("Rank role")).await?;
+                created += 1;
+                role.id
+            }
+        };
+        settings.bound.insert(level, role_id.into());
+    }
+    return Ok(created);
+}
+
+///Reads the role settings a guild has saved
+async fn load_settings(ctx: &Context, guild_id: GuildId) -> RoleSettings {
+    let data_lock = get_user_data_lock(ctx.clone()).await;
+    let data = data_lock.read().await;
+    return data.guild(guild_id.into()).map(|ledger| ledger.role_settings()).unwrap_or_default();
+}
+
+///Saves changed role settings
+async fn save_settings(ctx: &Context, guild_id: GuildId, settings: &RoleSettings) {
+    let data_lock = get_user_data_lock(ctx.clone()).await;
+    let mut data = data_lock.write().await;
+    let ledger = data.guild_mut(guild_id.into());
+    ledger.role_mode = settings.mode;
+    ledger.rank_roles = settings.bound.clone();
+    ledger.cosmetic_roles = settings.cosmetic.clone();
+    data.mark_guild_changed(guild_id.into());
+}
+
+pub struct RankRolesCommand;
+#[async_trait]
+impl Command for RankRolesCommand {
+    fn name(&self) -> &'static str {
+        return "rankroles";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Binds a rank to a role so renaming the role doesn't break it, creates missing rank roles, or lists them";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("level", ArgKind::Integer, "The level of the rank"),
+            ArgSpec::optional("role", ArgKind::Role, "The role to give at that level"),
+            ArgSpec::optional("cosmetic", ArgKind::Flag, "Bind or unbind the level's cosmetic role instead, for the highest role mode"),
+            ArgSpec::optional("unbind", ArgKind::Flag, "Go back to finding the level's role by name"),
+            ArgSpec::optional("create", ArgKind::Flag, "Bind every rank, creating roles for ranks that have none")
+        ];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_ROLES;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        let mut settings = load_settings(&ctx, guild_id).await;
+        let level = args.integer("level");
+        if level.is_some_and(|level| level < 1 || level > ranks.ranks.len() as i64) {
+            return Err(CommandError::Invalid(format!("The levels go from 1 to {}", ranks.ranks.len())));
+        }
+        let mut out = String::new();
+        if args.flag("create") {
+            let created = create_missing(&ctx, guild_id, &ranks, &mut settings).await?;
+            out += &format!("Bound every rank, {} roles had to be created.\n", created);
+        } else if let Some(level) = level {
+            let roles = if args.flag("cosmetic") {&mut settings.cosmetic} else {&mut settings.bound};
+            if args.flag("unbind") {
+                roles.remove(&(level as u64));
+            } else if let Some(role_id) = args.role("role") {
+                roles.insert(level as u64, role_id.into());
+            } else {
+                return Err(CommandError::Invalid("Give a role to bind the level to, or --unbind".to_string()));
+            }
+        }
+        if args.flag("create") || level.is_some() {
+            save_settings(&ctx, guild_id, &settings).await;
+        }
+        let rank_roles = RankRoles::load(&ctx, guild_id, &settings, &ranks).await;
+        out += &describe(&ranks, &settings, &rank_roles);
+        reply(ctx, &cmd, out).await;
+        return Ok(());
+    }
+}
+
+pub struct RoleModeCommand;
+#[async_trait]
+impl Command for RoleModeCommand {
+    fn name(&self) -> &'static str {
+        return "rolemode";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Chooses whether members keep only their current rank role, every rank role, or the highest plus cosmetic ones";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("mode", ArgKind::Text, "replace, stack or highest")];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_ROLES;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let mut settings = load_settings(&ctx, guild_id).await;
+        let Some(name) = args.text("mode") else {
+            reply(ctx, &cmd, format!("The role mode is **{}**, {}", settings.mode.name(), settings.mode.description())).await;
+            return Ok(());
+        };
+        let Some(mode) = RoleMode::parse(&name) else {
+            let modes: Vec<&str> = RoleMode::ALL.iter().map(|mode| mode.name()).collect();
+            return Err(CommandError::Invalid(format!("\"{}\" isn't a role mode, try {}", name, modes.join(", "))));
+        };
+        settings.mode = mode;
+        save_settings(&ctx, guild_id, &settings).await;
+        let prefix = get_prefix(ctx.clone(), Some(guild_id.into())).await;
+        reply(ctx, &cmd, format!("The role mode is now **{}**, {}. Members get their roles changed as they level, or all at once with {}syncroles", mode.name(), mode.description(), prefix)).await;
+        return Ok(());
+    }
+}
+
+pub struct SyncRoles;
+#[async_trait]
+impl Command for SyncRoles {
+    fn name(&self) -> &'static str {
+        return "syncroles";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Fixes everyone's rank roles to match their level and the role mode, this also happens daily";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("dry", ArgKind::Flag, "Only report what would change")];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_ROLES;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let dry_run = args.flag("dry");
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        let data_lock = get_user_data_lock(ctx.clone()).await;
+        cmd.reply_after(&ctx, "Checking everyone's rank roles, this can take a while...".to_string(), async {
+            let report = reconcile_guild(&ctx, &data_lock, &ranks, guild_id, dry_run).await?;
+            return Ok(report.summary(dry_run));
+        }).await;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::RoleId;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn says_which_roles_are_bound() {
+        let ranks = RankTable::from_json(r#"{"ranks": [
+            {"threshold": 0, "name": "Fresh", "color": "37"},
+            {"threshold": 20, "name": "Regular", "role": "regulars", "color": "32"},
+            {"threshold": 50, "name": "Elder", "color": "33"}
+        ]}"#).unwrap();
+        let settings = RoleSettings { bound: BTreeMap::from([(1, 4)]), ..Default::default() };
+        let rank_roles = RankRoles { by_level: BTreeMap::from([(1, RoleId::new(4)), (2, RoleId::new(5))]), cosmetic: BTreeMap::from([(2, RoleId::new(8))]), ..Default::default() };
+        let out = describe(&ranks, &settings, &rank_roles);
+        assert_eq!(out.lines().skip(1).collect::<Vec<&str>>(), vec![
+            "Level 1 **Fresh**: <@&4>",
+            "Level 2 **Regular**: <@&5> (by name, not bound), cosmetic <@&8>",
+            "Level 3 **Elder**: no role, nothing is called \"Elder\""
+        ]);
+        assert!(out.starts_with("Role mode: **replace**"));
+    }
+}
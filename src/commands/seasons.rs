@@ -0,0 +1,246 @@
+use serenity::all::{CreateEmbed, CreateEmbedFooter, GuildId, Permissions, RoleId, UserId};
+use serenity::async_trait;
+use serenity::http::StatusCode;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::data::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+use crate::ranks::RankTable;
+use crate::roles::RankRoles;
+
+///Rows on one page of a past season's standings
+const PAGE_SIZE: usize = 20;
+
+pub struct EndSeason;
+#[async_trait]
+impl Command for EndSeason {
+    fn name(&self) -> &'static str {
+        return "endseason";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Archives everyone's XP as a finished season and resets it, optionally giving the top members a role";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("role", ArgKind::Role, "A role to give everyone who earned XP this season").named(),
+            ArgSpec::optional("top", ArgKind::Integer, "Only give the role to this many of the top members").named(),
+            ArgSpec::optional("name", ArgKind::Text, "A name for the season, if it didn't get one when it started")
+        ];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let top = args.integer("top");
+        if top.is_some_and(|top| top < 1) {
+            return Err(CommandError::Invalid("--top has to be at least 1".to_string()));
+        }
+        if top.is_some() && args.role("role").is_none() {
+            return Err(CommandError::Invalid("--top only picks who gets --role, give a role too".to_string()));
+        }
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        let (archive, settings) = {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let mut data = data_lock.write().await;
+            let ledger = data.guild_mut(guild_id.into());
+            if ledger.off_season {
+                return Err(CommandError::Invalid("There's no season running, start one with startseason".to_string()));
+            }
+            if let Some(name) = args.text("name") {
+                ledger.season_name.get_or_insert(name);
+            }
+            let archive = ledger.close_season(cmd.timestamp()).clone();
+            let settings = ledger.role_settings();
+            data.mark_ledger_changed(guild_id.into());
+            (archive, settings)
+        };
+
+        let winners: Vec<&SeasonStanding> = archive.standings.iter().take(top.map(|top| top as usize).unwrap_or(usize::MAX)).collect();
+        let mut out = format!("**{}** is over, {} members earned XP.", archive.title(), archive.standings.len());
+        if let Some(first) = archive.standings.first() {
+            out += &format!(" <@{}> finished first with {} xp.", first.id, first.xp);
+        }
+        if let Some(role_id) = args.role("role") {
+            out += &format!(" Giving <@&{}> to {} members.", role_id, winners.len());
+        }
+        out += " XP is paused until the next season starts.";
+
+        cmd.reply_after(&ctx, format!("Ending **{}** and taking away rank roles, this can take a while...", archive.title()), async {
+            let mut problems = vec![];
+            if let Some(role_id) = args.role("role") {
+                let given = give_role(&ctx, guild_id.into(), role_id, winners.iter().map(|standing| standing.id)).await;
+                println!("gave the {} season role to {} of {} members", archive.title(), given, winners.len());
+                if given < winners.len() {
+                    problems.push(format!("{} of {} members didn't get <@&{}>", winners.len() - given, winners.len(), role_id));
+                }
+            }
+            let rank_roles = RankRoles::load(&ctx, guild_id, &settings, &ranks).await;
+            let kept = remove_rank_roles(&ctx, guild_id, &rank_roles, &archive.standings).await;
+            if kept > 0 {
+                problems.push(format!("{} members kept rank roles that should have been taken away", kept));
+            }
+            if !problems.is_empty() {
+                out += &format!("\n{}, check the bot has Manage Roles and its role is above those roles. {}syncroles fixes rank roles later.", problems.join(" and "), get_prefix(ctx.clone(), Some(guild_id.into())).await);
+            }
+            return Ok(out);
+        }).await;
+        return Ok(());
+    }
+}
+
+pub struct StartSeason;
+#[async_trait]
+impl Command for StartSeason {
+    fn name(&self) -> &'static str {
+        return "startseason";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Starts the next season after one was ended, so XP is earned again";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("name", ArgKind::Text, "A name for the new season")];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let number = {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let mut data = data_lock.write().await;
+            let ledger = data.guild_mut(guild_id.into());
+            if !ledger.off_season {
+                return Err(CommandError::Invalid("A season is already running, end it with endseason first".to_string()));
+            }
+            ledger.start_season(args.text("name"), cmd.timestamp());
+            let number = ledger.seasons.len() + 1;
+            data.mark_guild_changed(guild_id.into());
+            number
+        };
+        reply(ctx, &cmd, format!("**{}** has started, everyone's back at 0 XP. Good luck!", season_title(number, args.text("name").as_deref()))).await;
+        return Ok(());
+    }
+}
+
+pub struct Seasons;
+#[async_trait]
+impl Command for Seasons {
+    fn name(&self) -> &'static str {
+        return "seasons";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Lists past seasons, or shows the final standings of one";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("season", ArgKind::Integer, "Which season's standings to show"),
+            ArgSpec::optional("page", ArgKind::Integer, "Which page of the standings to show")
+        ];
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        let embed = {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let data = data_lock.read().await;
+            let seasons = data.guild(guild_id.into()).map(|ledger| ledger.seasons.as_slice()).unwrap_or_default();
+            match args.integer("season") {
+                None => season_list(seasons),
+                Some(number) => {
+                    let Some(archive) = seasons.iter().find(|archive| archive.number as i64 == number) else {
+                        return Err(CommandError::Invalid(format!("There's no season {}, there have been {} so far", number, seasons.len())));
+                    };
+                    season_standings(archive, &ranks, args.integer("page").unwrap_or(1))
+                }
+            }
+        };
+        cmd.reply_embed(&ctx, embed).await;
+        return Ok(());
+    }
+}
+
+///One line per closed season, newest first
+fn season_list(seasons: &[SeasonArchive]) -> CreateEmbed {
+    if seasons.is_empty() {
+        return CreateEmbed::new().title("PAST SEASONS").description("No season has ended yet");
+    }
+    let mut description = String::new();
+    for archive in seasons.iter().rev() {
+        description += &format!("**{}**, ended <t:{}:D>, {} members", archive.title(), archive.ended.unix_timestamp(), archive.standings.len());
+        if let Some(first) = archive.standings.first() {
+            description += &format!(", won by {} with {} xp", first.name, first.xp);
+        }
+        description += "\n";
+    }
+    return CreateEmbed::new().title("PAST SEASONS").description(description);
+}
+
+///A page of a closed season's final standings
+fn season_standings(archive: &SeasonArchive, ranks: &RankTable, page: i64) -> CreateEmbed {
+    let pages = archive.standings.len().div_ceil(PAGE_SIZE).max(1);
+    let page = (page.max(1) as usize).min(pages);
+    let rows: String = archive.standings.iter().enumerate().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE)
+        .map(|(index, standing)| format!("\u{001b}[1;{}m#{} {}: {} xp\u{001b}[0m\n", ranks.color(standing.level), index + 1, standing.name, standing.xp))
+        .collect();
+    let description = if rows.is_empty() {"Nobody earned XP that season".to_string()} else {format!("```ansi\n{}```", rows)};
+    return CreateEmbed::new().title(archive.title().to_uppercase()).description(description).footer(CreateEmbedFooter::new(format!("Page {} of {}", page, pages)));
+}
+
+///Gives a role to each user, returning how many got it
+async fn give_role(ctx: &Context, guild_id: u64, role_id: RoleId, users: impl Iterator<Item = u64>) -> usize {
+    let mut given = 0;
+    for user_id in users {
+        match ctx.http.add_member_role(guild_id.into(), UserId::new(user_id), role_id, Some("Season reward")).await {
+            Ok(()) => given += 1,
+            Err(why) => println!("Error giving season role to {}: {why:?}", user_id)
+        }
+    }
+    return given;
+}
+
+///Takes away the rank roles people held at the end of a season, since their levels were reset.
+///Returns how many members still have some of them.
+async fn remove_rank_roles(ctx: &Context, guild_id: GuildId, rank_roles: &RankRoles, standings: &[SeasonStanding]) -> usize {
+    let mut kept = 0;
+    for standing in standings.iter().filter(|standing| standing.level > 0) {
+        let mut failed = false;
+        for role_id in rank_roles.wanted(standing.level) {
+            match ctx.http.remove_member_role(guild_id, UserId::new(standing.id), role_id, Some("Season ended")).await {
+                Ok(()) => {},
+                // They left, so they don't have any of the roles anymore either
+                Err(why) if discord_code(&why) == Some(UNKNOWN_MEMBER) => break,
+                // The role was deleted, the rest can still be taken away
+                Err(why) if discord_status(&why) == Some(StatusCode::NOT_FOUND) => continue,
+                Err(why) => {
+                    println!("Error removing rank role from {}: {why:?}", standing.id);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            kept += 1;
+        }
+    }
+    return kept;
+}
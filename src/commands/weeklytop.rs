@@ -0,0 +1,77 @@
+use serenity::all::{Permissions, Timestamp};
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::data::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+
+///Most members a weekly announcement can list
+const MAX_COUNT: i64 = 25;
+
+pub struct WeeklyTop;
+#[async_trait]
+impl Command for WeeklyTop {
+    fn name(&self) -> &'static str {
+        return "weeklytop";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Announces the most active members in a channel every Monday, or shows the current setting";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("channel", ArgKind::Channel, "Where to announce"),
+            ArgSpec::optional("count", ArgKind::Integer, "How many members to list, 5 by default"),
+            ArgSpec::optional("off", ArgKind::Flag, "Stop announcing")
+        ];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let count = args.integer("count");
+        if count.is_some_and(|count| !(1..=MAX_COUNT).contains(&count)) {
+            return Err(CommandError::Invalid(format!("The count has to be between 1 and {}", MAX_COUNT)));
+        }
+        let data_lock = get_user_data_lock(ctx.clone()).await;
+        let out = {
+            let mut data = data_lock.write().await;
+            let ledger = data.guild_mut(guild_id.into());
+            if args.flag("off") {
+                ledger.weekly_top = None;
+                data.mark_guild_changed(guild_id.into());
+                "Weekly announcements are off".to_string()
+            } else if let Some(channel_id) = args.channel("channel") {
+                let count = count.unwrap_or(5);
+                // Start from the current week so the first announcement is for a full week
+                ledger.weekly_top = Some(WeeklyTopSettings { channel_id: channel_id.into(), count: count as usize, next_week: week_number(day_number(Timestamp::now())) });
+                data.mark_guild_changed(guild_id.into());
+                format!("The top {} members will be announced in <#{}> every Monday", count, channel_id)
+            } else if let Some(count) = count {
+                let Some(settings) = ledger.weekly_top.as_mut() else {
+                    return Err(CommandError::Invalid("Weekly announcements are off, give a channel along with the count".to_string()));
+                };
+                settings.count = count as usize;
+                let channel_id = settings.channel_id;
+                data.mark_guild_changed(guild_id.into());
+                format!("The top {} members will be announced in <#{}> every Monday", count, channel_id)
+            } else {
+                match &ledger.weekly_top {
+                    Some(settings) => format!("The top {} members are announced in <#{}> every Monday", settings.count, settings.channel_id),
+                    None => "Weekly announcements are off, give a channel to turn them on".to_string()
+                }
+            }
+        };
+        reply(ctx, &cmd, out).await;
+        return Ok(());
+    }
+}
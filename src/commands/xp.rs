@@ -0,0 +1,80 @@
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+
+pub struct Xp;
+#[async_trait]
+impl Command for Xp {
+    fn name(&self) -> &'static str {
+        return "xp";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Says how much XP you or someone else has, and how much until the next level";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("user", ArgKind::Member, "Whose XP to show, by mention, id or name")];
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+        let data = get_target_user_data(ctx.clone(), guild_id.into(), &cmd, &args, "user").await?;
+
+        let mut until_next_level = 0;
+        for threshold in get_level_thresholds(&ranks) {
+            if data.xp < threshold {
+                until_next_level = threshold - data.xp;
+                break;
+            }
+        }
+        let out = format!("{} has **{}** XP. {}'s next level is in **{}** XP.", data.name, data.xp, data.name, until_next_level);
+
+        reply(ctx, &cmd, out).await;
+        return Ok(());
+    }
+}
+
+pub struct XpCooldown;
+#[async_trait]
+impl Command for XpCooldown {
+    fn name(&self) -> &'static str {
+        return "xpcooldown";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Says how much time is left until the next XP drop for you or someone else";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![ArgSpec::optional("user", ArgKind::Member, "Whose cooldown to show, by mention, id or name")];
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let data = get_target_user_data(ctx.clone(), guild_id.into(), &cmd, &args, "user").await?;
+        let policy = {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let data = data_lock.read().await;
+            data.guild(guild_id.into()).map(|ledger| ledger.xp_policy.clone()).unwrap_or_default()
+        };
+        let remaining = policy.cooldown_left(data.last_message_timestamp, cmd.timestamp());
+        let out = if remaining > 0 {
+            format!("{}'s XP Cooldown Expires in {} seconds", data.name, remaining)
+        } else {
+            format!("{} can earn XP right now", data.name)
+        };
+
+        reply(ctx, &cmd, out).await;
+        return Ok(());
+    }
+}
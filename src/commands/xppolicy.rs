@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use serenity::all::Permissions;
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::error::*;
+use crate::framework::*;
+use crate::invocation::*;
+use crate::xp::XpPolicy;
+
+///Describes a policy in a few lines, for showing the current settings
+fn describe(policy: &XpPolicy) -> String {
+    let amount = if policy.min_xp == policy.max_xp {format!("{} xp", policy.min_xp)} else {format!("{} to {} xp", policy.min_xp, policy.max_xp)};
+    let mut out = format!("Messages earn {} at most once every {} seconds", amount, policy.cooldown);
+    if policy.min_length > 0 {
+        out += &format!(", if they're at least {} characters long or have an attachment", policy.min_length);
+    }
+    if policy.attachment_bonus > 0 {
+        out += &format!(", plus {} xp for attachments", policy.attachment_bonus);
+    }
+    out += ".";
+    let mut multipliers: Vec<String> = policy.channel_multipliers.iter().map(|(id, multiplier)| format!("<#{}> x{}", id, multiplier)).collect();
+    multipliers.extend(policy.role_multipliers.iter().map(|(id, multiplier)| format!("<@&{}> x{}", id, multiplier)));
+    if !multipliers.is_empty() {
+        multipliers.sort();
+        out += &format!("\nMultipliers: {}", multipliers.join(", "));
+    }
+    out += &format!("\n{}", describe_exclusions(policy));
+    return out;
+}
+
+///Lists the channels, categories and roles that don't earn XP
+fn describe_exclusions(policy: &XpPolicy) -> String {
+    let mut excluded: Vec<String> = policy.no_xp_channels.iter().map(|id| format!("<#{}>", id)).collect();
+    excluded.extend(policy.no_xp_roles.iter().map(|id| format!("<@&{}>", id)));
+    if excluded.is_empty() {
+        return "XP is earned everywhere".to_string();
+    }
+    return format!("No XP for: {}", excluded.join(", "));
+}
+
+///Reads a multiplier like `1.5` or `x2`
+fn parse_multiplier(text: Option<String>) -> Result<f64, CommandError> {
+    let text = text.unwrap_or_default();
+    return text.trim_start_matches(['x', 'X']).parse().map_err(|_| CommandError::Invalid(format!("\"{}\" isn't a multiplier, try something like 1.5", text)));
+}
+
+///Sets a multiplier, 1 takes the entry out since it's the same as having none
+fn set_multiplier(multipliers: &mut HashMap<u64, f64>, id: u64, multiplier: f64) {
+    if multiplier == 1.0 {
+        multipliers.remove(&id);
+    } else {
+        multipliers.insert(id, multiplier);
+    }
+}
+
+///Applies a change to a copy of the guild's policy and only keeps it if it still makes sense
+async fn change_policy(ctx: &Context, guild_id: u64, change: impl FnOnce(&mut XpPolicy)) -> Result<XpPolicy, CommandError> {
+    let data_lock = get_user_data_lock(ctx.clone()).await;
+    let mut data = data_lock.write().await;
+    let ledger = data.guild_mut(guild_id);
+    let mut policy = ledger.xp_policy.clone();
+    change(&mut policy);
+    policy.validate().map_err(CommandError::Invalid)?;
+    ledger.xp_policy = policy.clone();
+    data.mark_guild_changed(guild_id);
+    return Ok(policy);
+}
+
+pub struct Policy;
+#[async_trait]
+impl Command for Policy {
+    fn name(&self) -> &'static str {
+        return "xppolicy";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Changes how much XP messages earn and how often, or shows the current rules";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("cooldown", ArgKind::Integer, "Seconds between messages that earn XP").named(),
+            ArgSpec::optional("min", ArgKind::Integer, "Least XP a message can earn").named(),
+            ArgSpec::optional("max", ArgKind::Integer, "Most XP a message can earn").named(),
+            ArgSpec::optional("minlength", ArgKind::Integer, "Characters a message needs to earn XP").named(),
+            ArgSpec::optional("attachment", ArgKind::Integer, "Extra XP for messages with an attachment").named()
+        ];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let names = ["cooldown", "min", "max", "minlength", "attachment"];
+        if let Some(name) = names.iter().find(|name| args.integer(name).is_some_and(|value| value < 0)) {
+            return Err(CommandError::Invalid(format!("--{} can't be negative", name)));
+        }
+        if names.iter().all(|name| args.integer(name).is_none()) {
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let out = {
+                let data = data_lock.read().await;
+                describe(&data.guild(guild_id.into()).map(|ledger| ledger.xp_policy.clone()).unwrap_or_default())
+            };
+            reply(ctx, &cmd, out).await;
+            return Ok(());
+        }
+        let policy = change_policy(&ctx, guild_id.into(), |policy| {
+            if let Some(cooldown) = args.integer("cooldown") {
+                policy.cooldown = cooldown;
+            }
+            if let Some(min) = args.integer("min") {
+                policy.min_xp = min as u64;
+            }
+            if let Some(max) = args.integer("max") {
+                policy.max_xp = max as u64;
+            }
+            if let Some(min_length) = args.integer("minlength") {
+                policy.min_length = min_length as usize;
+            }
+            if let Some(bonus) = args.integer("attachment") {
+                policy.attachment_bonus = bonus as u64;
+            }
+        }).await?;
+        reply(ctx, &cmd, describe(&policy)).await;
+        return Ok(());
+    }
+}
+
+pub struct ChannelMultiplier;
+#[async_trait]
+impl Command for ChannelMultiplier {
+    fn name(&self) -> &'static str {
+        return "channelmultiplier";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Multiplies the XP earned in a channel or category, 1 goes back to normal";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::required("channel", ArgKind::Channel, "The channel or category"),
+            ArgSpec::required("multiplier", ArgKind::Text, "Like 2 or 0.5, 0 stops XP there")
+        ];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let Some(channel_id) = args.channel("channel") else {
+            return Err(CommandError::Invalid("Which channel?".to_string()));
+        };
+        let multiplier = parse_multiplier(args.text("multiplier"))?;
+        change_policy(&ctx, guild_id.into(), |policy| set_multiplier(&mut policy.channel_multipliers, channel_id.into(), multiplier)).await?;
+        reply(ctx, &cmd, format!("XP earned in <#{}> is multiplied by {}", channel_id, multiplier)).await;
+        return Ok(());
+    }
+}
+
+pub struct RoleMultiplier;
+#[async_trait]
+impl Command for RoleMultiplier {
+    fn name(&self) -> &'static str {
+        return "rolemultiplier";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Multiplies the XP earned by members with a role, 1 goes back to normal";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::required("role", ArgKind::Role, "The role"),
+            ArgSpec::required("multiplier", ArgKind::Text, "Like 2 or 0.5, the highest of a member's roles applies")
+        ];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let Some(role_id) = args.role("role") else {
+            return Err(CommandError::Invalid("Which role?".to_string()));
+        };
+        let multiplier = parse_multiplier(args.text("multiplier"))?;
+        change_policy(&ctx, guild_id.into(), |policy| set_multiplier(&mut policy.role_multipliers, role_id.into(), multiplier)).await?;
+        reply(ctx, &cmd, format!("XP earned by <@&{}> is multiplied by {}", role_id, multiplier)).await;
+        return Ok(());
+    }
+}
+
+pub struct NoXp;
+#[async_trait]
+impl Command for NoXp {
+    fn name(&self) -> &'static str {
+        return "noxp";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Stops XP in a channel or category, or for members with a role, or lists where XP is off";
+    }
+
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("channel", ArgKind::Channel, "A channel or category where messages shouldn't earn XP"),
+            ArgSpec::optional("role", ArgKind::Role, "A role whose members shouldn't earn XP").named(),
+            ArgSpec::optional("remove", ArgKind::Flag, "Let the channel or role earn XP again")
+        ];
+    }
+
+    fn permission(&self) -> Permissions {
+        return Permissions::MANAGE_GUILD;
+    }
+
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult {
+        let Some(guild_id) = cmd.guild_id() else {
+            return Err(CommandError::GuildOnly);
+        };
+        let channel_id = args.channel("channel").map(u64::from);
+        let role_id = args.role("role").map(u64::from);
+        if channel_id.is_none() && role_id.is_none() {
+            if args.flag("remove") {
+                return Err(CommandError::Invalid("Give a channel or --role to remove".to_string()));
+            }
+            let data_lock = get_user_data_lock(ctx.clone()).await;
+            let out = {
+                let data = data_lock.read().await;
+                describe_exclusions(&data.guild(guild_id.into()).map(|ledger| ledger.xp_policy.clone()).unwrap_or_default())
+            };
+            reply(ctx, &cmd, out).await;
+            return Ok(());
+        }
+        let remove = args.flag("remove");
+        let policy = change_policy(&ctx, guild_id.into(), |policy| {
+            for (excluded, id) in [(&mut policy.no_xp_channels, channel_id), (&mut policy.no_xp_roles, role_id)] {
+                if let Some(id) = id {
+                    if remove {
+                        excluded.remove(&id);
+                    } else {
+                        excluded.insert(id);
+                    }
+                }
+            }
+        }).await?;
+        reply(ctx, &cmd, describe_exclusions(&policy)).await;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_multipliers() {
+        assert_eq!(parse_multiplier(Some("1.5".to_string())).unwrap(), 1.5);
+        assert_eq!(parse_multiplier(Some("x2".to_string())).unwrap(), 2.0);
+        assert!(parse_multiplier(Some("lots".to_string())).is_err());
+        let mut multipliers = HashMap::from([(1, 2.0)]);
+        set_multiplier(&mut multipliers, 1, 1.0);
+        assert!(multipliers.is_empty());
+    }
+
+    #[test]
+    fn lists_exclusions() {
+        assert_eq!(describe_exclusions(&XpPolicy::default()), "XP is earned everywhere");
+        let policy = XpPolicy { no_xp_channels: [4].into(), no_xp_roles: [7].into(), ..Default::default() };
+        assert_eq!(describe_exclusions(&policy), "No XP for: <#4>, <@&7>");
+    }
+}
@@ -1,84 +1,644 @@
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::File, io::Write, sync::Arc};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, sync::Arc};
+use serde_json::Value;
 use serenity::all::{GuildChannel, Timestamp, UserId};
 
 use serenity::prelude::*;
 
-use crate::commandlib::*;
+use crate::announcements::{opted_out, AnnouncementSettings};
+use crate::migrations::*;
+use crate::ranks::{RankTable, RankTables};
+use crate::roles::{RankRoles, RoleMode, RoleSettings};
+use crate::storage::StorageError;
+use crate::xp::XpPolicy;
+
+///How many days of per-day XP each user keeps, the longest window anything looks back over
+pub const DAILY_XP_DAYS: i64 = 90;
+
+///The day a timestamp falls on, counted in whole UTC days since the unix epoch
+pub fn day_number(timestamp: Timestamp) -> i64 {
+    return timestamp.unix_timestamp().div_euclid(86400);
+}
+
+///The week a day falls in, weeks start on Monday and the epoch fell on a Thursday
+pub fn week_number(day: i64) -> i64 {
+    return (day + 3).div_euclid(7);
+}
+
+///The Monday a week starts on
+pub fn week_start(week: i64) -> i64 {
+    return week * 7 - 3;
+}
 
 pub struct GlobalData;
 impl TypeMapKey for GlobalData {
     type Value = Arc<RwLock<Data>>;
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+///What the save task hasn't written yet, so a save only copies and writes what changed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pending {
+    ///Everything, after a migration or on a first run
+    pub all: bool,
+    ///`last_timestamp` or the migrated users waiting for a guild
+    pub meta: bool,
+    ///Guilds whose settings changed
+    pub settings: BTreeSet<u64>,
+    ///Guilds where users were removed or most of them changed, every user there gets written
+    pub ledgers: BTreeSet<u64>,
+    ///Single users by guild
+    pub users: BTreeSet<(u64, u64)>
+}
+impl Pending {
+    pub fn is_empty(&self) -> bool {
+        return !self.all && !self.meta && self.settings.is_empty() && self.ledgers.is_empty() && self.users.is_empty();
+    }
+
+    ///Adds changes that couldn't be saved back, so the next save tries them again
+    pub fn merge(&mut self, other: Pending) {
+        self.all |= other.all;
+        self.meta |= other.meta;
+        self.settings.extend(other.settings);
+        self.ledgers.extend(other.ledgers);
+        self.users.extend(other.users);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Data {
-    pub data: HashMap<u64, UserData>,
+    ///Schema version of the saved document, see `migrations`
+    pub version: u64,
+    pub guilds: HashMap<u64, GuildData>,
+    ///Users from a pre-guild stats.txt that haven't been assigned to a guild yet
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub unassigned: HashMap<u64, UserData>,
     pub last_timestamp: Timestamp,
-    pub changed: bool
+    ///What the save task hasn't written yet
+    #[serde(skip)]
+    pub pending: Pending,
+    ///How many changes happened since the last save
+    #[serde(skip)]
+    pub changes: u64
 }
 impl Data {
-    pub fn from_data(data: HashMap<u64, UserData>, last_timestamp: Timestamp) -> Data {
-        return Data {data, last_timestamp, changed: false};
+    pub fn from_data(guilds: HashMap<u64, GuildData>, unassigned: HashMap<u64, UserData>, last_timestamp: Timestamp) -> Data {
+        return Data {version: CURRENT_VERSION, guilds, unassigned, last_timestamp, pending: Pending::default(), changes: 0};
+    }
+
+    ///Empty data for a brand new deployment. History from the last `backfill_days` days gets scanned on `ready`.
+    pub fn first_run(backfill_days: i64) -> Data {
+        let last_timestamp = Timestamp::from_unix_timestamp(Timestamp::now().unix_timestamp() - backfill_days.max(0) * 86400).unwrap_or(Timestamp::now());
+        let mut data = Data::from_data(HashMap::new(), HashMap::new(), last_timestamp);
+        data.mark_changed();
+        return data;
+    }
+
+    ///Whether there's anything the save task hasn't written yet
+    pub fn changed(&self) -> bool {
+        return !self.pending.is_empty();
+    }
+
+    ///Marks everything as needing a save
+    pub fn mark_changed(&mut self) {
+        self.pending.all = true;
+        self.changes += 1;
+    }
+
+    ///Marks one user's record as needing a save
+    pub fn mark_user_changed(&mut self, guild_id: u64, user_id: u64) {
+        self.pending.users.insert((guild_id, user_id));
+        self.changes += 1;
+    }
+
+    ///Marks a guild's settings as needing a save, not its users
+    pub fn mark_guild_changed(&mut self, guild_id: u64) {
+        self.pending.settings.insert(guild_id);
+        self.changes += 1;
+    }
+
+    ///Marks a guild's settings and every user there as needing a save, for changes that touch most of them
+    pub fn mark_ledger_changed(&mut self, guild_id: u64) {
+        self.pending.settings.insert(guild_id);
+        self.pending.ledgers.insert(guild_id);
+        self.changes += 1;
+    }
+
+    ///Moves `last_timestamp` forward to a message's time, it never goes back
+    pub fn advance_last_timestamp(&mut self, timestamp: Timestamp) {
+        if self.last_timestamp.unix_timestamp() < timestamp.unix_timestamp() {
+            self.last_timestamp = timestamp;
+            self.pending.meta = true;
+        }
+    }
+
+    ///Parses a stats.txt, upgrading it if it was saved by an older version
+    pub fn from_json(contents: &str) -> Result<Data, StorageError> {
+        return Data::from_document(serde_json::from_str(contents)?);
+    }
+
+    ///Builds the data from a saved document of any version, older documents are marked to be saved again
+    pub fn from_document(document: Value) -> Result<Data, StorageError> {
+        let (document, version) = migrate(document).map_err(StorageError::Migration)?;
+        let mut data: Data = serde_json::from_value(document)?;
+        if version < CURRENT_VERSION {
+            data.mark_changed();
+        }
+        return Ok(data);
+    }
+
+    ///Gets the ledger for a guild, if anyone there has been recorded
+    pub fn guild(&self, guild_id: u64) -> Option<&GuildData> {
+        return self.guilds.get(&guild_id);
+    }
+
+    ///Gets the ledger for a guild, creating it if needed
+    pub fn guild_mut(&mut self, guild_id: u64) -> &mut GuildData {
+        return self.guilds.entry(guild_id).or_default();
+    }
+
+    ///Moves the users of a pre-guild stats.txt into the guild LEGACY_GUILD_ID, if it's set
+    pub fn assign_unassigned_from_env(&mut self, ranks: &RankTables) {
+        if let Ok(guild_id) = std::env::var("LEGACY_GUILD_ID") {
+            if let Ok(guild_id) = guild_id.parse::<u64>() {
+                self.assign_unassigned(guild_id, &ranks.for_guild(guild_id));
+            } else {
+                println!("LEGACY_GUILD_ID is not a valid guild id");
+            }
+        }
+    }
+
+    ///Moves the users of a pre-guild stats.txt into a guild's ledger
+    pub fn assign_unassigned(&mut self, guild_id: u64, ranks: &RankTable) {
+        if self.unassigned.is_empty() {
+            return;
+        }
+        println!("assigning {} migrated users to guild {}", self.unassigned.len(), guild_id);
+        self.assign_unassigned_where(guild_id, ranks, |_| true);
+    }
+
+    ///Moves the users of a pre-guild stats.txt that `members` holds into a guild's ledger, returning how many moved
+    pub fn assign_unassigned_members(&mut self, guild_id: u64, members: &HashSet<u64>, ranks: &RankTable) -> usize {
+        return self.assign_unassigned_where(guild_id, ranks, |user_id| members.contains(&user_id));
+    }
+
+    ///Anyone who chatted before being assigned already has a record in the guild, the two get merged into one
+    fn assign_unassigned_where(&mut self, guild_id: u64, ranks: &RankTable, assign: impl Fn(u64) -> bool) -> usize {
+        let (users, rest) = std::mem::take(&mut self.unassigned).into_iter().partition::<HashMap<u64, UserData>, _>(|(id, _)| assign(*id));
+        self.unassigned = rest;
+        if users.is_empty() {
+            return 0;
+        }
+        let moved = users.len();
+        let ledger = self.guild_mut(guild_id);
+        for (id, user) in users {
+            let user = match ledger.users.remove(&id) {
+                Some(mut current) => {
+                    current.merge(user);
+                    current
+                },
+                None => user
+            };
+            ledger.users.insert(id, user);
+            if let Some(user) = ledger.users.get_mut(&id) {
+                user.level = ranks.level(user.xp);
+            }
+        }
+        self.pending.meta = true;
+        self.mark_ledger_changed(guild_id);
+        return moved;
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+///The XP ledger of a single guild
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct GuildData {
+    pub users: HashMap<u64, UserData>,
+    ///What commands start with here, `!` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    ///Where and how the top members of each week get announced, if at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weekly_top: Option<WeeklyTopSettings>,
+    ///Name of the season running now, if it was given one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub season_name: Option<String>,
+    ///When the season running now started, unknown for the first season
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub season_started: Option<Timestamp>,
+    ///Whether a season was closed and the next one hasn't started yet, nobody earns XP meanwhile
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub off_season: bool,
+    ///Final standings of every closed season, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seasons: Vec<SeasonArchive>,
+    ///How XP is earned here
+    #[serde(default, skip_serializing_if = "XpPolicy::is_default")]
+    pub xp_policy: XpPolicy,
+    ///Role id given at each level, set with `rankroles` so renaming a role doesn't lose it
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rank_roles: BTreeMap<u64, u64>,
+    ///Role id given at each level as a cosmetic badge, see `RoleMode::Highest`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cosmetic_roles: BTreeMap<u64, u64>,
+    ///Whether members keep rank roles they've passed
+    #[serde(default, skip_serializing_if = "RoleMode::is_default")]
+    pub role_mode: RoleMode,
+    ///Where and how rank ups get announced
+    #[serde(default, skip_serializing_if = "AnnouncementSettings::is_default")]
+    pub announcements: AnnouncementSettings
+}
+
+///How a closed season ended
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SeasonArchive {
+    ///Seasons are numbered from 1 in the order they closed
+    pub number: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started: Option<Timestamp>,
+    pub ended: Timestamp,
+    ///Everyone who earned XP that season, most first
+    pub standings: Vec<SeasonStanding>
+}
+impl SeasonArchive {
+    pub fn title(&self) -> String {
+        return season_title(self.number, self.name.as_deref());
+    }
+}
+
+///"Season 3" or "Season 3: Summer"
+pub fn season_title(number: usize, name: Option<&str>) -> String {
+    return match name {
+        Some(name) => format!("Season {}: {}", number, name),
+        None => format!("Season {}", number)
+    };
+}
+
+///One user's result in a closed season
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SeasonStanding {
+    pub id: u64,
+    pub name: String,
+    pub xp: u64,
+    pub level: u64
+}
+
+///Settings for announcing the most active members when a week ends
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WeeklyTopSettings {
+    pub channel_id: u64,
+    ///How many members to list
+    pub count: usize,
+    ///The first week that hasn't been announced yet, see `week_number`
+    pub next_week: i64
+}
+impl GuildData {
+    ///A copy of everything but the users, for saving settings without copying the whole ledger
+    pub fn settings(&mut self) -> GuildData {
+        let users = std::mem::take(&mut self.users);
+        let settings = self.clone();
+        self.users = users;
+        return settings;
+    }
+
+    ///The rank role settings, copied so they can be used after letting go of the ledger
+    pub fn role_settings(&self) -> RoleSettings {
+        return RoleSettings { mode: self.role_mode, bound: self.rank_roles.clone(), cosmetic: self.cosmetic_roles.clone() };
+    }
+
+    ///Everyone in the guild, most XP first, ties broken by id so the order is stable
+    pub fn by_xp(&self) -> Vec<&UserData> {
+        return self.ranked_by(|user| user.xp).into_iter().map(|(user, _)| user).collect();
+    }
+
+    ///Everyone in the guild with their score, highest first, ties broken by id
+    pub fn ranked_by(&self, score: impl Fn(&UserData) -> u64) -> Vec<(&UserData, u64)> {
+        let mut users: Vec<(&UserData, u64)> = self.users.values().map(|user| (user, score(user))).collect();
+        users.sort_by_key(|(user, score)| (std::cmp::Reverse(*score), user.id));
+        return users;
+    }
+
+    ///Where someone is on the XP leaderboard, starting at 1
+    pub fn position(&self, user_id: u64) -> Option<usize> {
+        return self.by_xp().iter().position(|user| user.id == user_id).map(|index| index + 1);
+    }
+
+    ///Archives everyone's XP and level as a finished season, then resets them for the next one.
+    ///Nobody earns XP until `start_season` is called.
+    pub fn close_season(&mut self, ended: Timestamp) -> &SeasonArchive {
+        let standings = self.by_xp().into_iter()
+            .filter(|user| user.xp > 0)
+            .map(|user| SeasonStanding { id: user.id, name: user.name.clone(), xp: user.xp, level: user.level })
+            .collect();
+        let archive = SeasonArchive { number: self.seasons.len() + 1, name: self.season_name.take(), started: self.season_started.take(), ended, standings };
+        for user in self.users.values_mut() {
+            user.xp = 0;
+            user.level = 0;
+            user.daily_xp.clear();
+        }
+        self.off_season = true;
+        self.seasons.push(archive);
+        return self.seasons.last().expect("a season was just archived");
+    }
+
+    ///Starts the next season after one was closed
+    pub fn start_season(&mut self, name: Option<String>, started: Timestamp) {
+        self.off_season = false;
+        self.season_name = name;
+        self.season_started = Some(started);
+    }
+
+    ///Finds whoever a name most likely means, ignoring case. An exact match wins, then names starting with it,
+    ///then names containing it, and among several candidates the one with the most XP.
+    pub fn find_user_by_name(&self, name: &str) -> Option<&UserData> {
+        let name = name.trim().to_lowercase();
+        if name.is_empty() {
+            return None;
+        }
+        let matches: [fn(&str, &str) -> bool; 3] = [
+            |candidate, name| candidate == name,
+            |candidate, name| candidate.starts_with(name),
+            |candidate, name| candidate.contains(name)
+        ];
+        for matches in matches {
+            let best = self.users.values()
+                .filter(|user| matches(&user.name.to_lowercase(), &name))
+                .max_by_key(|user| (user.xp, std::cmp::Reverse(user.id)));
+            if best.is_some() {
+                return best;
+            }
+        }
+        return None;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct UserData {
     pub id: u64,
     pub name: String,
     pub last_message_timestamp: Timestamp,
     pub xp: u64,
     pub level: u64,
-    pub user_data: HashMap<String, String>
+    pub user_data: HashMap<String, String>,
+    ///Every message counted in the guild, whether or not it earned XP
+    #[serde(default)]
+    pub messages: u64,
+    ///When the user first showed up on the ledger, unknown for users from before this was tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<Timestamp>,
+    ///XP earned on each of the last `DAILY_XP_DAYS` days, keyed by `day_number`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub daily_xp: BTreeMap<i64, u64>
 }
+impl UserData {
+    ///A user seen for the first time, with the message that put them on the ledger counted but no XP yet
+    pub fn new(id: u64, name: String, timestamp: Timestamp) -> UserData {
+        return UserData { id, name, last_message_timestamp: timestamp, xp: 0, level: 0, user_data: HashMap::new(), messages: 1, first_seen: Some(timestamp), daily_xp: BTreeMap::new() };
+    }
 
-pub struct DiscordHandler;
-impl DiscordHandler {
-    pub async fn update(&self, ctx: Context) {
-        let data_lock = {
-            let data_read = ctx.data.read().await;
-            data_read.get::<GlobalData>().expect("Expected Data in TypeMap.").clone()
+    ///Folds another record of the same user into this one, which stays the newer of the two. Everything
+    ///counted is added up, and this record's own storage wins over the other's.
+    pub fn merge(&mut self, other: UserData) {
+        self.xp = self.xp.saturating_add(other.xp);
+        self.messages = self.messages.saturating_add(other.messages);
+        // Unknown means from before it was tracked, which is earlier than anything known
+        self.first_seen = match (self.first_seen, other.first_seen) {
+            (Some(first_seen), Some(other)) => Some(if other.unix_timestamp() < first_seen.unix_timestamp() {other} else {first_seen}),
+            _ => None
         };
-        let data_guard = &data_lock.read().await;
-        let data= Data::from_data(data_guard.data.clone(), data_guard.last_timestamp);
-        if let Ok(filedata) = serde_json::to_string_pretty(&data) {
-            if let Ok(mut file) = File::create("stats.txt"){
-                if let Ok(_) = file.write_all(filedata.as_bytes()) {
-                    println!("saved");
-                } else {
-                    println!("save failed - writing to file");
-                }
-            } else {
-                println!("save failed - file creation");
-            }
-        } else {
-            println!("save failed - json creation");
-        }
-    }
-}
-
-pub async fn update_level(ctx: Context, user_data: &mut UserData, channel: GuildChannel){
-    if let Ok(guild) = ctx.http.get_guild(channel.guild_id).await {
-        if let Ok(member) = ctx.http.get_member(guild.id, user_data.id.into()).await {
-            if level(user_data.xp) != user_data.level {
-                remove_role(ctx.clone(), member.clone(), guild.id, &rank(user_data.level)).await;
-                if rank(user_data.level) < rank(level(user_data.xp)) && rank(level(user_data.xp)) != String::new() {
-                    if let Ok(user) = ctx.http.get_user(UserId::from(user_data.id)).await{
-                        if !user.bot {
-                            if let Err(why) = channel.say(&ctx.http, format!("GG <@{}>, you just advanced to **{}** !", user_data.id, rank(level(user_data.xp)))).await {
-                                println!("Error sending message: {why:?}");
-                            }
-                        }
-                    }
-                }
-                user_data.level = level(user_data.xp);
-            }
-            if let Some(role_id) = get_role(guild.clone(), &rank(user_data.level)).await {
-                if !member.roles.contains(&role_id.id)  {
-                    add_role(ctx, member, guild.id, &rank(user_data.level)).await;
-                }
-            }
+        if self.last_message_timestamp.unix_timestamp() < other.last_message_timestamp.unix_timestamp() {
+            self.last_message_timestamp = other.last_message_timestamp;
+        }
+        for (key, value) in other.user_data {
+            self.user_data.entry(key).or_insert(value);
+        }
+        for (day, xp) in other.daily_xp {
+            let total = self.daily_xp.entry(day).or_default();
+            *total = total.saturating_add(xp);
+        }
+    }
+
+    ///Adds XP earned at a time, to the total and to that day, forgetting days too old to matter
+    pub fn add_xp(&mut self, amount: u64, timestamp: Timestamp) {
+        self.xp = self.xp.saturating_add(amount);
+        let today = day_number(timestamp);
+        let daily = self.daily_xp.entry(today).or_default();
+        *daily = daily.saturating_add(amount);
+        self.daily_xp.retain(|day, _| *day > today - DAILY_XP_DAYS);
+    }
+
+    ///XP earned over the `days` days ending with `today`
+    pub fn xp_in_last_days(&self, days: i64, today: i64) -> u64 {
+        return self.xp_between(today - days + 1, today);
+    }
+
+    ///XP earned from `first` to `last`, both days included
+    pub fn xp_between(&self, first: i64, last: i64) -> u64 {
+        if first > last {
+            return 0;
+        }
+        return self.daily_xp.range(first..=last).map(|(_, xp)| xp).sum();
+    }
+}
+
+pub struct DiscordHandler;
+
+///A rank up announcement worked out by `update_level`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RankUp {
+    pub text: String,
+    pub color: (u8, u8, u8)
+}
+
+///What `update_level` changed, sent to Discord with `send` once the data lock has been let go
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelUpdate {
+    pub user_id: u64,
+    pub level: u64,
+    ///Unset when they didn't rank up, the rank has no name, or they opted out of announcements
+    pub rank_up: Option<RankUp>,
+    pub announcements: AnnouncementSettings
+}
+impl LevelUpdate {
+    ///Announces the rank up the way the guild wants and gives them the roles of their rank. `channel` is where
+    ///the message that changed their XP was sent, `member_roles` are the roles they have now so only roles that
+    ///change cost a request. Returns the roles they have afterwards, unset when a change didn't go through and
+    ///only Discord knows.
+    pub async fn send(&self, ctx: &Context, channel: &GuildChannel, rank_roles: &RankRoles, member_roles: &[u64]) -> Option<Vec<u64>> {
+        if let Some(rank_up) = &self.rank_up {
+            self.announcements.announce(ctx, channel, self.user_id, rank_up.text.clone(), rank_up.color).await;
+        }
+        let changes = rank_roles.changes(self.level, member_roles);
+        if !changes.apply(&ctx.http, channel.guild_id, UserId::new(self.user_id), "Rank changed").await {
+            return None;
+        }
+        return Some(changes.applied_to(member_roles));
+    }
+}
+
+///Brings a user's level up to date and works out what to tell Discord. Nothing is sent here so this can run
+///under the data lock, the caller sends the returned update after letting go of it.
+pub fn update_level(user_data: &mut UserData, ranks: &RankTable, announcements: &AnnouncementSettings) -> LevelUpdate {
+    let new_level = ranks.level(user_data.xp);
+    let mut rank_up = None;
+    if user_data.level < new_level && !ranks.rank(new_level).is_empty() && !opted_out(user_data) {
+        let text = announcements.text(ranks.announcement(new_level), user_data, &ranks.rank(new_level), new_level);
+        rank_up = Some(RankUp { text, color: ranks.rgb(new_level) });
+    }
+    user_data.level = new_level;
+    return LevelUpdate { user_id: user_data.id, level: new_level, rank_up, announcements: announcements.clone() };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u64, name: &str, xp: u64) -> UserData {
+        let mut user = UserData::new(id, name.to_string(), Timestamp::from_unix_timestamp(0).unwrap());
+        user.xp = xp;
+        return user;
+    }
+
+    #[test]
+    fn level_updates_announce_rank_ups_unless_opted_out() {
+        let ranks = RankTable::default();
+        let announcements = AnnouncementSettings::default();
+        let mut climber = user(1, "climber", 10);
+        let update = update_level(&mut climber, &ranks, &announcements);
+        assert_eq!(climber.level, 1);
+        assert_eq!(update.level, 1);
+        assert_eq!(update.rank_up.map(|rank_up| rank_up.color), Some(ranks.rgb(1)));
+        assert_eq!(update_level(&mut climber, &ranks, &announcements).rank_up, None);
+
+        let mut quiet = user(2, "quiet", 10);
+        quiet.user_data.insert(crate::announcements::OPT_OUT_KEY.to_string(), crate::announcements::OPTED_OUT.into());
+        let update = update_level(&mut quiet, &ranks, &announcements);
+        assert_eq!((quiet.level, update.rank_up), (1, None));
+    }
+
+    #[test]
+    fn leaderboard_positions_are_stable() {
+        let mut ledger = GuildData::default();
+        for user in [user(1, "a", 5), user(2, "b", 50), user(3, "c", 5)] {
+            ledger.users.insert(user.id, user);
+        }
+        assert_eq!(ledger.by_xp().iter().map(|user| user.id).collect::<Vec<u64>>(), vec![2, 1, 3]);
+        assert_eq!(ledger.position(3), Some(3));
+        assert_eq!(ledger.position(9), None);
+    }
+
+    #[test]
+    fn closing_a_season_archives_and_resets() {
+        let mut ledger = GuildData { season_name: Some("Spring".to_string()), ..Default::default() };
+        for (id, xp) in [(1, 5), (2, 50), (3, 0)] {
+            let mut user = user(id, &format!("user {}", id), 0);
+            user.add_xp(xp, Timestamp::from_unix_timestamp(86400).unwrap());
+            user.level = xp / 10;
+            user.user_data.insert("color".to_string(), "blue".to_string());
+            ledger.users.insert(id, user);
+        }
+        let ended = Timestamp::from_unix_timestamp(1000).unwrap();
+        let archive = ledger.close_season(ended).clone();
+        assert_eq!(archive.title(), "Season 1: Spring");
+        assert_eq!(archive.standings, vec![
+            SeasonStanding { id: 2, name: "user 2".to_string(), xp: 50, level: 5 },
+            SeasonStanding { id: 1, name: "user 1".to_string(), xp: 5, level: 0 }
+        ]);
+        assert!(ledger.off_season);
+        assert!(ledger.users.values().all(|user| user.xp == 0 && user.level == 0 && user.daily_xp.is_empty()));
+        assert_eq!(ledger.users[&1].user_data["color"], "blue");
+
+        ledger.start_season(None, ended);
+        assert!(!ledger.off_season);
+        assert_eq!(ledger.close_season(ended).title(), "Season 2");
+        assert_eq!(ledger.seasons.len(), 2);
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // 1970-01-01 was a Thursday, 1970-01-05 a Monday
+        assert_eq!(week_number(0), 0);
+        assert_eq!(week_number(3), 0);
+        assert_eq!(week_number(4), 1);
+        assert_eq!(week_number(10), 1);
+        assert_eq!(week_number(11), 2);
+        assert_eq!(week_start(1), 4);
+        assert_eq!(week_number(week_start(2900)), 2900);
+        // 2026-10-19 is a Monday
+        let monday = day_number(Timestamp::parse("2026-10-19T12:00:00Z").unwrap());
+        assert_eq!(week_start(week_number(monday)), monday);
+        assert_eq!(week_number(monday - 1) + 1, week_number(monday));
+    }
+
+    #[test]
+    fn daily_xp_covers_recent_windows() {
+        let day = |n: i64| Timestamp::from_unix_timestamp(n * 86400 + 3600).unwrap();
+        let mut user = user(1, "a", 0);
+        user.add_xp(1, day(100));
+        user.add_xp(2, day(107));
+        user.add_xp(3, day(110));
+        user.add_xp(1, day(110));
+        assert_eq!(user.xp, 7);
+        assert_eq!(user.xp_in_last_days(1, 110), 4);
+        assert_eq!(user.xp_in_last_days(7, 110), 6);
+        assert_eq!(user.xp_in_last_days(30, 110), 7);
+        assert_eq!(user.xp_in_last_days(7, 120), 0);
+        assert_eq!(user.xp_between(100, 107), 3);
+        assert_eq!(user.xp_between(108, 100), 0);
+        user.add_xp(1, day(100 + DAILY_XP_DAYS));
+        assert_eq!(user.daily_xp.keys().next(), Some(&107));
+    }
+
+    #[test]
+    fn assigns_only_migrated_members() {
+        let mut data = Data::from_data(HashMap::new(), HashMap::from([(1, user(1, "a", 5)), (2, user(2, "b", 50))]), Timestamp::from_unix_timestamp(0).unwrap());
+        assert_eq!(data.assign_unassigned_members(7, &HashSet::from([2, 3]), &RankTable::default()), 1);
+        assert_eq!(data.guilds[&7].users.keys().copied().collect::<Vec<u64>>(), vec![2]);
+        assert_eq!(data.unassigned.keys().copied().collect::<Vec<u64>>(), vec![1]);
+        assert!(data.pending.ledgers.contains(&7) && data.pending.meta);
+        assert_eq!(data.assign_unassigned_members(8, &HashSet::from([2]), &RankTable::default()), 0);
+        assert!(!data.guilds.contains_key(&8));
+    }
+
+    #[test]
+    fn claiming_merges_with_the_record_made_meanwhile() {
+        let ranks = RankTable::default();
+        let mut migrated = user(1, "Old name", 30);
+        migrated.messages = 40;
+        migrated.first_seen = None;
+        migrated.user_data.insert("color".to_string(), "blue".to_string());
+        migrated.user_data.insert("pronouns".to_string(), "they".to_string());
+        let mut data = Data::from_data(HashMap::new(), HashMap::from([(1, migrated)]), Timestamp::from_unix_timestamp(0).unwrap());
+        let later = Timestamp::from_unix_timestamp(86400).unwrap();
+        let mut current = UserData::new(1, "New name".to_string(), later);
+        current.add_xp(2, later);
+        current.user_data.insert("color".to_string(), "red".to_string());
+        data.guild_mut(7).users.insert(1, current);
+
+        assert_eq!(data.assign_unassigned_members(7, &HashSet::from([1]), &ranks), 1);
+        let user = &data.guilds[&7].users[&1];
+        assert_eq!((user.xp, user.messages, user.level), (32, 41, ranks.level(32)));
+        assert_eq!(user.name, "New name");
+        assert_eq!((user.first_seen, user.last_message_timestamp), (None, later));
+        assert_eq!((user.user_data["color"].as_str(), user.user_data["pronouns"].as_str()), ("red", "they"));
+        assert_eq!(user.xp_in_last_days(1, 1), 2);
+    }
+
+    #[test]
+    fn finds_users_by_name() {
+        let mut ledger = GuildData::default();
+        for user in [user(1, "Lemon", 5), user(2, "Lemonade", 50), user(3, "Sour Lemon", 500), user(4, "Luna", 1)] {
+            ledger.users.insert(user.id, user);
         }
+        let found = |name| ledger.find_user_by_name(name).map(|user| user.id);
+        assert_eq!(found("lemon"), Some(1));
+        assert_eq!(found("LEMONA"), Some(2));
+        assert_eq!(found("lem"), Some(2));
+        assert_eq!(found("sour"), Some(3));
+        assert_eq!(found("una"), Some(4));
+        assert_eq!(found("mon"), Some(3));
+        assert_eq!(found("nobody"), None);
+        assert_eq!(found(" "), None);
     }
-}
\ No newline at end of file
+}
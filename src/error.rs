@@ -0,0 +1,37 @@
+use std::fmt;
+
+use serenity::all::UserId;
+
+///Why a command couldn't do what was asked. The registry answers with the `Display` text, so it's written for users.
+#[derive(Debug)]
+pub enum CommandError {
+    ///The command needs a server and was used in DMs
+    GuildOnly,
+    ///Nobody in the ledger matches a name
+    UnknownName(String),
+    ///The user exists but hasn't been recorded in this server, `author` says whether it's whoever ran the command
+    NoRecord { user_id: UserId, author: bool },
+    ///Something about the input that the argument parser can't check, the text is shown as is
+    Invalid(String),
+    ///Discord refused a request, this is logged and users get a generic apology
+    Discord(Box<serenity::Error>)
+}
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::GuildOnly => write!(f, "That only works in servers"),
+            CommandError::UnknownName(name) => write!(f, "I couldn't find anyone called \"{}\"", name),
+            CommandError::NoRecord { author: true, .. } => write!(f, "You haven't earned any XP here yet"),
+            CommandError::NoRecord { user_id, .. } => write!(f, "<@{}> hasn't earned any XP here yet", user_id),
+            CommandError::Invalid(why) => write!(f, "{}", why),
+            CommandError::Discord(_) => write!(f, "Something went wrong talking to Discord, try again in a bit")
+        }
+    }
+}
+impl From<serenity::Error> for CommandError {
+    fn from(why: serenity::Error) -> Self {
+        return CommandError::Discord(Box::new(why));
+    }
+}
+
+pub type CommandResult = Result<(), CommandError>;
@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serenity::all::{ChannelId, CommandDataOptionValue, CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, Permissions, RoleId, UserId};
+use serenity::async_trait;
+use serenity::prelude::*;
+
+use crate::commandlib::*;
+use crate::error::*;
+use crate::invocation::*;
+use crate::tokenizer::*;
+
+///What kind of value an argument takes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgKind {
+    ///A mention, a user id, or someone's name, as the last positional argument the name can have spaces
+    Member,
+    ///A role mention or a role id
+    Role,
+    ///A channel mention or a channel id
+    Channel,
+    ///A whole number
+    Integer,
+    ///Any text, as the last positional argument it takes everything that's left
+    Text,
+    ///One word out of a fixed list, offered as choices to slash commands
+    Choice(&'static [&'static str]),
+    ///A `--name` switch that's either there or not, never positional
+    Flag
+}
+
+///Describes one argument a command takes
+#[derive(Clone, Debug)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+    pub description: &'static str,
+    ///Only settable as `--name value`, so it never takes a positional token
+    pub named: bool,
+    ///A flag that can also be written as just its name, like `me`
+    pub bare: bool
+}
+impl ArgSpec {
+    pub fn required(name: &'static str, kind: ArgKind, description: &'static str) -> ArgSpec {
+        return ArgSpec { name, kind, required: true, description, named: false, bare: false };
+    }
+
+    pub fn optional(name: &'static str, kind: ArgKind, description: &'static str) -> ArgSpec {
+        return ArgSpec { name, kind, required: false, description, named: false, bare: false };
+    }
+
+    ///Makes the argument `--name value` only
+    pub fn named(mut self) -> ArgSpec {
+        self.named = true;
+        return self;
+    }
+
+    ///Lets a flag be written as its bare name too
+    pub fn bare(mut self) -> ArgSpec {
+        self.bare = true;
+        return self;
+    }
+
+    ///How the argument shows up in help, `<name>` when required and `[name]` when optional
+    pub fn usage(&self) -> String {
+        if self.kind == ArgKind::Flag {
+            return if self.bare {format!("[{}]", self.name)} else {format!("[--{}]", self.name)};
+        }
+        if let ArgKind::Choice(choices) = self.kind {
+            return if self.required {format!("<{}>", choices.join("|"))} else {format!("[{}]", choices.join("|"))};
+        }
+        if self.named {
+            return if self.required {format!("--{} <{}>", self.name, self.name)} else {format!("[--{} <{}>]", self.name, self.name)};
+        }
+        return if self.required {format!("<{}>", self.name)} else {format!("[{}]", self.name)};
+    }
+}
+
+///A parsed argument value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arg {
+    User(UserId),
+    Role(RoleId),
+    Channel(ChannelId),
+    Integer(i64),
+    Text(String),
+    Flag(bool)
+}
+
+///The parsed arguments of a command, looked up by name
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    values: HashMap<&'static str, Arg>
+}
+impl Args {
+    pub fn user(&self, name: &str) -> Option<UserId> {
+        return match self.values.get(name) {
+            Some(Arg::User(user_id)) => Some(*user_id),
+            _ => None
+        };
+    }
+
+    pub fn role(&self, name: &str) -> Option<RoleId> {
+        return match self.values.get(name) {
+            Some(Arg::Role(role_id)) => Some(*role_id),
+            _ => None
+        };
+    }
+
+    pub fn channel(&self, name: &str) -> Option<ChannelId> {
+        return match self.values.get(name) {
+            Some(Arg::Channel(channel_id)) => Some(*channel_id),
+            _ => None
+        };
+    }
+
+    ///Whether a `--name` flag was given
+    pub fn flag(&self, name: &str) -> bool {
+        return matches!(self.values.get(name), Some(Arg::Flag(true)));
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        return match self.values.get(name) {
+            Some(Arg::Integer(number)) => Some(*number),
+            _ => None
+        };
+    }
+
+    pub fn text(&self, name: &str) -> Option<String> {
+        return match self.values.get(name) {
+            Some(Arg::Text(text)) => Some(text.to_owned()),
+            _ => None
+        };
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ArgError {
+    Missing(&'static str),
+    Invalid { name: &'static str, value: String, expected: String },
+    TooMany(Vec<String>),
+    UnknownFlag(String),
+    Tokenize(TokenizeError)
+}
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Missing(name) => write!(f, "missing {}", name),
+            ArgError::Invalid { name, value, expected } => write!(f, "{} should be {}, not \"{}\"", name, expected, value),
+            ArgError::TooMany(extra) => write!(f, "didn't expect \"{}\"", extra.join(" ")),
+            ArgError::UnknownFlag(name) => write!(f, "there's no --{} option", name),
+            ArgError::Tokenize(why) => write!(f, "{}", why)
+        }
+    }
+}
+
+///Everything about a command in one place, the registry uses it for parsing, dispatch, help and slash commands
+#[async_trait]
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+    ///Other names that run the same command with `!`
+    fn aliases(&self) -> &'static [&'static str] {
+        return &[];
+    }
+    fn description(&self) -> &'static str;
+    fn args(&self) -> Vec<ArgSpec> {
+        return vec![];
+    }
+    ///Permissions a member needs to run the command
+    fn permission(&self) -> Permissions {
+        return Permissions::empty();
+    }
+    ///Errors get turned into a reply by the registry, so a command can just `?` its way out
+    async fn run(&self, ctx: Context, cmd: Invocation, args: Args) -> CommandResult;
+}
+
+///Reads one argument value from a token
+pub fn parse_arg(spec: &ArgSpec, token: &Token) -> Result<Arg, ArgError> {
+    let invalid = |expected: &str| ArgError::Invalid { name: spec.name, value: token.to_string(), expected: expected.to_string() };
+    return match (spec.kind, token) {
+        (ArgKind::Member, Token::User(user_id)) => Ok(Arg::User(*user_id)),
+        (ArgKind::Member, Token::Number(id)) if *id != 0 => Ok(Arg::User(UserId::new(*id))),
+        (ArgKind::Member, Token::Text(name)) => Ok(Arg::Text(name.to_owned())),
+        (ArgKind::Member, _) => Err(invalid("a mention, user id or name")),
+        (ArgKind::Role, Token::Role(role_id)) => Ok(Arg::Role(*role_id)),
+        (ArgKind::Role, Token::Number(id)) if *id != 0 => Ok(Arg::Role(RoleId::new(*id))),
+        (ArgKind::Role, _) => Err(invalid("a role mention or role id")),
+        (ArgKind::Channel, Token::Channel(channel_id)) => Ok(Arg::Channel(*channel_id)),
+        (ArgKind::Channel, Token::Number(id)) if *id != 0 => Ok(Arg::Channel(ChannelId::new(*id))),
+        (ArgKind::Channel, _) => Err(invalid("a channel mention or channel id")),
+        (ArgKind::Integer, token) => token.to_string().parse().map(Arg::Integer).map_err(|_| invalid("a number")),
+        (ArgKind::Text, token) => Ok(Arg::Text(token.to_string())),
+        (ArgKind::Choice(choices), token) => match choices.iter().find(|choice| choice.eq_ignore_ascii_case(&token.to_string())) {
+            Some(choice) => Ok(Arg::Text(choice.to_string())),
+            None => Err(invalid(&format!("one of {}", choices.join(", "))))
+        },
+        (ArgKind::Flag, _) => Err(invalid("nothing"))
+    };
+}
+
+///Matches tokens against a command's argument list. `--name value` sets an argument by name,
+///everything else fills the positional arguments in order. An optional positional argument the next
+///token doesn't fit is left out, so `!leaderboard 3` can skip the sort.
+pub fn parse_args(specs: &[ArgSpec], tokens: &[Token]) -> Result<Args, ArgError> {
+    let mut args = Args::default();
+    let mut positional = vec![];
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        let bare = specs.iter().find(|spec| spec.bare && matches!(token, Token::Text(word) if word.eq_ignore_ascii_case(spec.name)));
+        if let Some(spec) = bare {
+            args.values.insert(spec.name, Arg::Flag(true));
+            continue;
+        }
+        let Token::Flag(name) = token else {
+            positional.push(token);
+            continue;
+        };
+        let Some(spec) = specs.iter().find(|spec| spec.name == name) else {
+            return Err(ArgError::UnknownFlag(name.to_owned()));
+        };
+        if spec.kind == ArgKind::Flag {
+            args.values.insert(spec.name, Arg::Flag(true));
+            continue;
+        }
+        match tokens.next() {
+            Some(Token::Flag(_)) | None => return Err(ArgError::Missing(spec.name)),
+            Some(value) => {
+                args.values.insert(spec.name, parse_arg(spec, value)?);
+            }
+        }
+    }
+
+    if let Some(missing) = specs.iter().find(|spec| spec.named && spec.required && !args.values.contains_key(spec.name)) {
+        return Err(ArgError::Missing(missing.name));
+    }
+    let positional_specs: Vec<&ArgSpec> = specs.iter().filter(|spec| spec.kind != ArgKind::Flag && !spec.named && !args.values.contains_key(spec.name)).collect();
+    let mut positional = positional.into_iter().peekable();
+    for (index, spec) in positional_specs.iter().enumerate() {
+        let last = index == positional_specs.len() - 1;
+        let takes_rest = matches!(spec.kind, ArgKind::Text | ArgKind::Member) && last;
+        let value = if takes_rest {
+            let rest: Vec<&Token> = positional.by_ref().collect();
+            match rest.as_slice() {
+                [] => None,
+                [token] => Some(parse_arg(spec, token)?),
+                rest => Some(Arg::Text(rest.iter().map(|token| token.to_string()).collect::<Vec<String>>().join(" ")))
+            }
+        } else {
+            match positional.peek().map(|token| parse_arg(spec, token)) {
+                Some(Ok(value)) => {
+                    positional.next();
+                    Some(value)
+                },
+                Some(Err(_)) if !spec.required && !last => None,
+                Some(Err(why)) => return Err(why),
+                None => None
+            }
+        };
+        match value {
+            Some(value) => {
+                args.values.insert(spec.name, value);
+            },
+            None => {
+                if spec.required {
+                    return Err(ArgError::Missing(spec.name));
+                }
+            }
+        }
+    }
+    let extra: Vec<String> = positional.map(|token| token.to_string()).collect();
+    if !extra.is_empty() {
+        return Err(ArgError::TooMany(extra));
+    }
+    return Ok(args);
+}
+
+///Reads a slash command's options using a command's argument list
+pub fn parse_slash_args(specs: &[ArgSpec], command: &CommandInteraction) -> Result<Args, ArgError> {
+    let mut args = Args::default();
+    for spec in specs {
+        let value = command.data.options.iter().find(|option| option.name == spec.name).map(|option| match &option.value {
+            CommandDataOptionValue::User(user_id) => Ok(Arg::User(*user_id)),
+            CommandDataOptionValue::Role(role_id) => Ok(Arg::Role(*role_id)),
+            CommandDataOptionValue::Channel(channel_id) => Ok(Arg::Channel(*channel_id)),
+            CommandDataOptionValue::Integer(number) => Ok(Arg::Integer(*number)),
+            CommandDataOptionValue::Boolean(set) => Ok(Arg::Flag(*set)),
+            CommandDataOptionValue::String(text) => Ok(Arg::Text(text.to_owned())),
+            other => Err(ArgError::Invalid { name: spec.name, value: format!("{:?}", other), expected: "a supported option".to_string() })
+        });
+        match value {
+            Some(value) => {
+                args.values.insert(spec.name, value?);
+            },
+            None => {
+                if spec.required {
+                    return Err(ArgError::Missing(spec.name));
+                }
+            }
+        }
+    }
+    return Ok(args);
+}
+
+///Every command the bot knows
+pub struct Registry {
+    commands: Vec<Box<dyn Command>>
+}
+impl Registry {
+    pub fn new(commands: Vec<Box<dyn Command>>) -> Registry {
+        return Registry { commands };
+    }
+
+    ///Finds a command by its name or one of its aliases
+    pub fn find(&self, name: &str) -> Option<&dyn Command> {
+        return self.commands.iter().find(|command| command.name() == name || command.aliases().contains(&name)).map(|command| command.as_ref());
+    }
+
+    ///How to call a command, like `!xp [user]`
+    pub fn usage(command: &dyn Command, prefix: &str) -> String {
+        let mut usage = format!("{}{}", prefix, command.name());
+        for arg in command.args() {
+            usage += &format!(" {}", arg.usage());
+        }
+        return usage;
+    }
+
+    pub fn help(&self, prefix: &str) -> String {
+        let mut message = "Help (every command also works as a /command):\n".to_string();
+        for command in &self.commands {
+            let aliases = if command.aliases().is_empty() {"".to_string()} else {format!(" (also {}{})", prefix, command.aliases().join(&format!(", {}", prefix)))};
+            message += &format!("{}: {}{}\n", Registry::usage(command.as_ref(), prefix), command.description(), aliases);
+        }
+        return message;
+    }
+
+    ///Builds an application command for every command
+    pub fn slash_commands(&self) -> Vec<CreateCommand> {
+        let mut commands = vec![];
+        for command in &self.commands {
+            let mut slash = CreateCommand::new(command.name()).description(command.description().chars().take(100).collect::<String>());
+            if !command.permission().is_empty() {
+                slash = slash.default_member_permissions(command.permission());
+            }
+            for arg in command.args() {
+                let kind = match arg.kind {
+                    ArgKind::Member => CommandOptionType::User,
+                    ArgKind::Role => CommandOptionType::Role,
+                    ArgKind::Channel => CommandOptionType::Channel,
+                    ArgKind::Integer => CommandOptionType::Integer,
+                    ArgKind::Text | ArgKind::Choice(_) => CommandOptionType::String,
+                    ArgKind::Flag => CommandOptionType::Boolean
+                };
+                let mut option = CreateCommandOption::new(kind, arg.name, arg.description).required(arg.required);
+                if let ArgKind::Choice(choices) = arg.kind {
+                    for choice in choices {
+                        option = option.add_string_choice(*choice, *choice);
+                    }
+                }
+                slash = slash.add_option(option);
+            }
+            commands.push(slash);
+        }
+        return commands;
+    }
+
+    ///Runs a prefixed command, answering with usage if the arguments don't fit
+    pub async fn dispatch(&self, ctx: Context, line: CommandLine, cmd: Invocation) {
+        let Some(command) = self.find(&line.name) else {
+            return;
+        };
+        let args = line.tokens.map_err(ArgError::Tokenize).and_then(|tokens| parse_args(&command.args(), &tokens));
+        self.run(ctx, command, cmd, args).await;
+    }
+
+    ///Runs a slash command
+    pub async fn dispatch_slash(&self, ctx: Context, interaction: CommandInteraction) {
+        let Some(command) = self.find(&interaction.data.name) else {
+            println!("unknown command: {}", interaction.data.name);
+            return;
+        };
+        let args = parse_slash_args(&command.args(), &interaction);
+        self.run(ctx, command, Invocation::Slash(Box::new(interaction)), args).await;
+    }
+
+    async fn run(&self, ctx: Context, command: &dyn Command, cmd: Invocation, args: Result<Args, ArgError>) {
+        if !command.permission().is_empty() && !has_permission(ctx.clone(), &cmd, command.permission()).await {
+            reply(ctx, &cmd, "You don't have permission to use that command".to_string()).await;
+            return;
+        }
+        match args {
+            Ok(args) => {
+                if let Err(why) = command.run(ctx.clone(), cmd.clone(), args).await {
+                    if let CommandError::Discord(inner) = &why {
+                        println!("Error running {}: {inner:?}", command.name());
+                    }
+                    reply(ctx, &cmd, why.to_string()).await;
+                }
+            },
+            Err(why) => {
+                let prefix = match &cmd {
+                    Invocation::Message(_) => get_prefix(ctx.clone(), cmd.guild_id().map(u64::from)).await,
+                    Invocation::Slash(_) => "/".to_string()
+                };
+                reply(ctx, &cmd, format!("Usage: {}\n{}", Registry::usage(command, &prefix), why)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs() -> Vec<ArgSpec> {
+        return vec![
+            ArgSpec::optional("user", ArgKind::Member, ""),
+            ArgSpec::optional("page", ArgKind::Integer, ""),
+            ArgSpec::optional("all", ArgKind::Flag, "")
+        ];
+    }
+
+    #[test]
+    fn fills_positional_arguments_in_order() {
+        let args = parse_args(&specs(), &tokenize("<@5> 3").unwrap()).unwrap();
+        assert_eq!(args.user("user"), Some(UserId::new(5)));
+        assert_eq!(args.integer("page"), Some(3));
+        assert!(!args.flag("all"));
+        assert!(matches!(parse_args(&specs(), &tokenize("<@5> 3 4").unwrap()), Err(ArgError::TooMany(_))));
+        assert!(matches!(parse_args(&specs(), &tokenize("<@5> three").unwrap()), Err(ArgError::Invalid { name: "page", .. })));
+    }
+
+    #[test]
+    fn binds_flags_by_name() {
+        let args = parse_args(&specs(), &tokenize("--page 2 --all 12345").unwrap()).unwrap();
+        assert_eq!(args.integer("page"), Some(2));
+        assert_eq!(args.user("user"), Some(UserId::new(12345)));
+        assert!(args.flag("all"));
+        assert!(matches!(parse_args(&specs(), &tokenize("--nope").unwrap()), Err(ArgError::UnknownFlag(_))));
+        assert!(matches!(parse_args(&specs(), &tokenize("--page").unwrap()), Err(ArgError::Missing("page"))));
+    }
+
+    #[test]
+    fn last_text_argument_takes_the_rest() {
+        let specs = vec![ArgSpec::required("role", ArgKind::Role, ""), ArgSpec::required("name", ArgKind::Text, "")];
+        let args = parse_args(&specs, &tokenize(r#"<@&7> Some "Long Name""#).unwrap()).unwrap();
+        assert_eq!(args.role("role"), Some(RoleId::new(7)));
+        assert_eq!(args.text("name"), Some("Some Long Name".to_string()));
+        assert!(matches!(parse_args(&specs, &tokenize("<@&7>").unwrap()), Err(ArgError::Missing("name"))));
+    }
+
+    #[test]
+    fn named_arguments_never_take_positional_tokens() {
+        let specs = vec![ArgSpec::optional("role", ArgKind::Role, "").named(), ArgSpec::optional("name", ArgKind::Text, "")];
+        let args = parse_args(&specs, &tokenize("Summer Fun").unwrap()).unwrap();
+        assert_eq!(args.role("role"), None);
+        assert_eq!(args.text("name"), Some("Summer Fun".to_string()));
+        let args = parse_args(&specs, &tokenize("Summer --role <@&3> Fun").unwrap()).unwrap();
+        assert_eq!(args.role("role"), Some(RoleId::new(3)));
+        assert_eq!(args.text("name"), Some("Summer Fun".to_string()));
+        assert_eq!(specs[0].usage(), "[--role <role>]");
+        let specs = vec![ArgSpec::required("count", ArgKind::Integer, "").named()];
+        assert!(matches!(parse_args(&specs, &tokenize("").unwrap()), Err(ArgError::Missing("count"))));
+    }
+
+    #[test]
+    fn optional_arguments_that_dont_fit_are_skipped() {
+        let specs = vec![
+            ArgSpec::optional("sort", ArgKind::Choice(&["xp", "weekly"]), ""),
+            ArgSpec::optional("page", ArgKind::Integer, ""),
+            ArgSpec::optional("me", ArgKind::Flag, "").bare()
+        ];
+        let args = parse_args(&specs, &tokenize("3").unwrap()).unwrap();
+        assert_eq!((args.text("sort"), args.integer("page")), (None, Some(3)));
+        let args = parse_args(&specs, &tokenize("me WEEKLY 2").unwrap()).unwrap();
+        assert_eq!((args.text("sort"), args.integer("page"), args.flag("me")), (Some("weekly".to_string()), Some(2), true));
+        assert!(matches!(parse_args(&specs, &tokenize("sideways").unwrap()), Err(ArgError::Invalid { name: "page", .. })));
+        assert_eq!(specs.iter().map(|spec| spec.usage()).collect::<Vec<String>>(), vec!["[xp|weekly]", "[page]", "[me]"]);
+    }
+
+    #[test]
+    fn members_can_be_named() {
+        let specs = vec![ArgSpec::optional("user", ArgKind::Member, "")];
+        assert_eq!(parse_args(&specs, &tokenize("<@!9>").unwrap()).unwrap().user("user"), Some(UserId::new(9)));
+        assert_eq!(parse_args(&specs, &tokenize("9").unwrap()).unwrap().user("user"), Some(UserId::new(9)));
+        assert_eq!(parse_args(&specs, &tokenize("Lemon Tree").unwrap()).unwrap().text("user"), Some("Lemon Tree".to_string()));
+        assert_eq!(parse_args(&specs, &tokenize("").unwrap()).unwrap().text("user"), None);
+    }
+}
@@ -1,18 +1,21 @@
-use serenity::all::{Channel, Timestamp, UserId};
+use serenity::all::{Command, GuildChannel, Interaction};
 use serenity::futures::StreamExt;
 use serenity::{async_trait};
 use serenity::model::channel::Message;
 use serenity::model::gateway::Ready;
-use serenity::builder::{CreateEmbed, CreateMessage};
+
 use std::collections::HashMap;
-use regex::Regex;
+use std::collections::hash_map::Entry;
 
 use serenity::prelude::*;
 
 use crate::data::*;
 use crate::commandlib::*;
-use crate::commands::all_commands;
-use crate::commands::run_command;
+use crate::commands::{all_commands, dispatch_component};
+use crate::invocation::*;
+use crate::roles::RankRoles;
+use crate::tokenizer::*;
+use crate::xp::*;
 
 #[async_trait]
 impl EventHandler for DiscordHandler {
@@ -28,41 +31,35 @@ impl EventHandler for DiscordHandler {
                 let data_read = ctx.data.read().await;
                 data_read.get::<GlobalData>().expect("Expected Data in TypeMap.").clone()
             };
-            {
-                let mut data = data_lock.write().await;
-                if let Some(user) = data.data.get_mut(&user_id) {
-                    user.name = msg.author.display_name().to_string();
-                    if msg.timestamp.unix_timestamp() - user.last_message_timestamp.unix_timestamp() > 60 {
-                        user.last_message_timestamp = msg.timestamp;
-                        user.xp += 1;
-                        data.changed = true;
-                    }
-                    if data.last_timestamp.unix_timestamp() < msg.timestamp.unix_timestamp() {
-                        data.last_timestamp = msg.timestamp;
-                    }
-                } else {
-                    data.data.insert(user_id, UserData { id: user_id, name: msg.author.display_name().to_string(), last_message_timestamp: msg.timestamp, xp: 1, level: level(1), user_data: HashMap::new() });
-                    data.changed = true;
-                }
-                if let Some(user) = data.data.get_mut(&user_id) {
-                    if let Ok(channel) = msg.channel(&ctx.http).await {
-                        if let Some(guildchannel) = channel.guild() {
-                            update_level(ctx.clone(), user, guildchannel).await;
-                        }
-                    }
+            if let Some(guild_id) = msg.guild_id.filter(|_| from_person(&msg)) {
+                let ranks = get_rank_table(ctx.clone(), guild_id.into()).await;
+                let channel = msg.channel(&ctx).await.ok().and_then(|channel| channel.guild());
+                let role_ids = msg.member.as_ref().map(|member| member.roles.iter().map(|role_id| u64::from(*role_id)).collect()).unwrap_or_default();
+                let message = XpMessage::new(&msg, channel.as_ref(), role_ids);
+                let (update, role_settings) = {
+                    let mut data = data_lock.write().await;
+                    data.advance_last_timestamp(msg.timestamp);
+                    let ledger = data.guild_mut(guild_id.into());
+                    let award = award_xp(ledger, user_id, msg.author.display_name(), &message, &ranks);
+                    let role_settings = ledger.role_settings();
+                    let announcements = ledger.announcements.clone();
+                    let update = ledger.users.get_mut(&user_id).filter(|_| award.needs_level_update()).map(|user| update_level(user, &ranks, &announcements));
+                    // The message count changes with every message, even when no XP is awarded
+                    data.mark_user_changed(guild_id.into(), user_id);
+                    (update, role_settings)
+                };
+                // Discord is only told once the data is unlocked, so other messages and commands don't wait on it
+                if let (Some(update), Some(channel)) = (update, channel) {
+                    let rank_roles = RankRoles::load(&ctx, guild_id, &role_settings, &ranks).await;
+                    update.send(&ctx, &channel, &rank_roles, &message.role_ids).await;
                 }
             }
         }
         if let Ok(current_user) = ctx.http.get_current_user().await {
             if current_user.id != msg.author.id {
-                for (command_name, command_data) in all_commands() {
-                    let re = Regex::new(r"^!(\w+)(?: (\w+))*").unwrap();
-                    if let Some(captures) = re.captures(&msg.content.to_owned()) {
-                        if captures[1].to_string() == command_name {
-                            run_command(command_name, ctx.clone(), msg.clone(), captures.iter().skip(2).map(|c| if c.is_some() {c.unwrap().as_str().to_owned()} else {"".to_string()}).collect()).await;
-                            break;
-                        }
-                    }
+                let prefix = get_prefix(ctx.clone(), msg.guild_id.map(u64::from)).await;
+                if let Some(line) = parse_command_line(&msg.content, &prefix) {
+                    all_commands().dispatch(ctx.clone(), line, Invocation::Message(Box::new(msg.clone()))).await;
                 }
             }
         }
@@ -74,19 +71,15 @@ impl EventHandler for DiscordHandler {
                 println!("Error sending message: {why:?}");
             }
         }
-        {
-            let changed;
-            {
-                let data_lock = {
-                    let data_read = ctx.data.read().await;
-                    data_read.get::<GlobalData>().expect("Expected Data in TypeMap.").clone()
-                };
-                let data = data_lock.read().await;
-                changed = data.changed;
-            }
-            if changed {
-                self.update(ctx).await;
-            }
+    }
+
+    // Slash commands arrive as interactions, and go through the same commands as `!` messages.
+    // Button clicks on messages the commands sent arrive here too.
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::Command(command) => all_commands().dispatch_slash(ctx, command).await,
+            Interaction::Component(component) => dispatch_component(ctx, component).await,
+            _ => {}
         }
     }
 
@@ -97,24 +90,38 @@ impl EventHandler for DiscordHandler {
     // In this case, just print what the current user's username is.
     async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+        if let Err(why) = Command::set_global_commands(&ctx.http, all_commands().slash_commands()).await {
+            println!("Error registering slash commands: {why:?}");
+        }
         {
             let data_lock = {
                 let data_read = ctx.data.read().await;
                 data_read.get::<GlobalData>().expect("Expected Data in TypeMap.").clone()
             };
-            let mut data = data_lock.write().await;
-
-            let mut message_vec: Vec<(u64, Timestamp, String, Option<Channel>)> = vec![];
+            let mut message_vec: Vec<(u64, u64, String, XpMessage, GuildChannel)> = vec![];
 
             println!("step 1, checking for new messages");
             if let Ok(guilds) = ctx.http.clone().get_guilds(None, None).await {
+                let last_timestamp = {
+                    let only_guild = match guilds.as_slice() {
+                        [guild] => Some((u64::from(guild.id), get_rank_table(ctx.clone(), guild.id.into()).await)),
+                        _ => None
+                    };
+                    let mut data = data_lock.write().await;
+                    if let Some((guild_id, ranks)) = only_guild {
+                        data.assign_unassigned(guild_id, &ranks);
+                    } else if !data.unassigned.is_empty() {
+                        println!("{} migrated users aren't assigned to a guild, set LEGACY_GUILD_ID or have an admin run claimmigrated", data.unassigned.len());
+                    }
+                    data.last_timestamp
+                };
                 for guild_info in guilds {
                     println!("checking guild {:?}", guild_info.name);
                     if let Ok(guild) = ctx.http.clone().get_guild(guild_info.id).await {
                         if let Ok(channels) = guild.channels(ctx.http.clone()).await {
-                            for channel in channels.keys() {
-                                println!("checking channel {:?}", channel.name(&ctx.http).await);
-                                let mut messages = channel.messages_iter(ctx.http.clone()).boxed();
+                            for (channel_id, channel) in channels {
+                                println!("checking channel {:?}", channel.name);
+                                let mut messages = channel_id.messages_iter(ctx.http.clone()).boxed();
                                 while let Some(message_result) = messages.next().await {
                                     println!("checking message");
                                     if message_vec.len() > 10000 {
@@ -122,12 +129,13 @@ impl EventHandler for DiscordHandler {
                                     }
                                     match message_result {
                                         Ok(message) => {
-                                            if message.timestamp > data.last_timestamp {
-                                                if let Ok(channel) = message.channel(ctx.http.clone()).await {
-                                                    message_vec.push((u64::from(message.author.id), message.timestamp, message.author.display_name().to_string(), Some(channel)));
-                                                } else {
-                                                    message_vec.push((u64::from(message.author.id), message.timestamp, message.author.display_name().to_string(), None));
+                                            if message.timestamp > last_timestamp {
+                                                if !from_person(&message) {
+                                                    continue;
                                                 }
+                                                // Roles are filled in when replaying, once per member rather than once per message
+                                                let xp_message = XpMessage::new(&message, Some(&channel), vec![]);
+                                                message_vec.push((u64::from(guild.id), u64::from(message.author.id), message.author.display_name().to_string(), xp_message, channel.clone()));
                                             }else{
                                                 break;
                                             }
@@ -144,34 +152,56 @@ impl EventHandler for DiscordHandler {
                 }
             }
             println!("step 2, {} messages to sift through", message_vec.len());
-            message_vec.sort_by_key(|k| k.1);
-            for (id, timestamp, name, channel) in message_vec {
-                if let Some(user) = data.data.get_mut(&id) {
-                    if user.last_message_timestamp.unix_timestamp() + 60 < timestamp.unix_timestamp() {
-                        user.last_message_timestamp = timestamp;
-                        user.xp += 1;
-                        if level(user.xp) != user.level && channel.is_some() {
-                            if let Some(guildchannel) = channel.unwrap().guild() {
-                                update_level(ctx.clone(), user, guildchannel).await;
-                            }
-                        }
-                    }
-                } else {
-                    data.data.insert(id, UserData { id: id, name: name, last_message_timestamp: timestamp, xp: 1, level: level(1), user_data: HashMap::new() });
-                    if let Some(user) = data.data.get_mut(&id) {
-                        if let Some(guildchannel) = channel.unwrap().guild() {
-                            update_level(ctx.clone(), user, guildchannel).await;
-                        }
-                    }
+            sort_for_replay(&mut message_vec, |k| &k.3);
+            let mut member_roles: HashMap<(u64, u64), Vec<u64>> = HashMap::new();
+            let mut rank_tables = HashMap::new();
+            for (guild_id, id, _, _, _) in &message_vec {
+                if let Entry::Vacant(entry) = member_roles.entry((*guild_id, *id)) {
+                    let roles = match ctx.http.get_member((*guild_id).into(), (*id).into()).await {
+                        Ok(member) => member.roles.iter().map(|role_id| u64::from(*role_id)).collect(),
+                        Err(_) => vec![]
+                    };
+                    entry.insert(roles);
+                }
+                if !rank_tables.contains_key(guild_id) {
+                    rank_tables.insert(*guild_id, get_rank_table(ctx.clone(), *guild_id).await);
                 }
-                if data.last_timestamp.unix_timestamp() < timestamp.unix_timestamp() {
-                    data.last_timestamp = timestamp;
+            }
+            // Everything Discord needs to hear about waits until the replay is done and the data is unlocked
+            let mut updates = vec![];
+            {
+                let mut data = data_lock.write().await;
+                for (guild_id, id, name, mut message, channel) in message_vec {
+                    let ranks = &rank_tables[&guild_id];
+                    message.role_ids = member_roles[&(guild_id, id)].clone();
+                    let timestamp = message.timestamp;
+                    let ledger = data.guild_mut(guild_id);
+                    let award = award_xp(ledger, id, &name, &message, ranks);
+                    let announcements = ledger.announcements.clone();
+                    if let Some(user) = ledger.users.get_mut(&id).filter(|_| award.needs_level_update()) {
+                        updates.push((guild_id, update_level(user, ranks, &announcements), ledger.role_settings(), channel));
+                    }
+                    data.mark_user_changed(guild_id, id);
+                    data.advance_last_timestamp(timestamp);
                 }
             }
+            for (guild_id, update, role_settings, channel) in updates {
+                let rank_roles = RankRoles::load(&ctx, guild_id.into(), &role_settings, &rank_tables[&guild_id]).await;
+                let key = (guild_id, update.user_id);
+                // Later rank ups of the same member are worked out from the roles this one left them with
+                let roles = match update.send(&ctx, &channel, &rank_roles, &member_roles[&key]).await {
+                    Some(roles) => roles,
+                    None => match ctx.http.get_member(guild_id.into(), update.user_id.into()).await {
+                        Ok(member) => member.roles.iter().map(|role_id| u64::from(*role_id)).collect(),
+                        Err(_) => vec![]
+                    }
+                };
+                member_roles.insert(key, roles);
+            }
             //scrape usernames on boot
             /*
-            for (userid, _) in &data.data.to_owned() {
-                if let Some(userdata) = data.data.get_mut(&userid) {
+            for (userid, _) in &ledger.users.to_owned() {
+                if let Some(userdata) = ledger.users.get_mut(&userid) {
                     if let Ok(user) = ctx.http.get_user(UserId::from(userid.to_owned())).await {
                         userdata.name = user.display_name().to_owned();
                     }
@@ -179,8 +209,5 @@ impl EventHandler for DiscordHandler {
             }
             */
         }
-
-
-        self.update(ctx).await;
     }
 }
\ No newline at end of file
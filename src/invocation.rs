@@ -0,0 +1,119 @@
+use serenity::all::{CommandInteraction, CreateActionRow, CreateAttachment, CreateEmbed, CreateInteractionResponse, CreateInteractionResponseFollowup, CreateInteractionResponseMessage, CreateMessage, GuildId, Timestamp, User};
+use serenity::model::channel::Message;
+use std::future::Future;
+
+use serenity::prelude::*;
+
+use crate::error::CommandError;
+
+///Where a command came from, either a `!` message or a slash command, so commands can answer either the same way
+#[derive(Clone, Debug)]
+pub enum Invocation {
+    Message(Box<Message>),
+    Slash(Box<CommandInteraction>)
+}
+impl Invocation {
+    pub fn guild_id(&self) -> Option<GuildId> {
+        return match self {
+            Invocation::Message(msg) => msg.guild_id,
+            Invocation::Slash(command) => command.guild_id
+        };
+    }
+
+    ///The user who ran the command
+    pub fn author(&self) -> &User {
+        return match self {
+            Invocation::Message(msg) => &msg.author,
+            Invocation::Slash(command) => &command.user
+        };
+    }
+
+    ///When the command was sent
+    pub fn timestamp(&self) -> Timestamp {
+        return match self {
+            Invocation::Message(msg) => msg.timestamp,
+            Invocation::Slash(command) => command.id.created_at()
+        };
+    }
+
+    ///Answers the command with text. Returns whether the message got sent.
+    pub async fn reply(&self, ctx: &Context, message: String) -> bool {
+        let result = match self {
+            Invocation::Message(msg) => msg.channel_id.say(&ctx.http, message).await.map(|_| ()),
+            Invocation::Slash(command) => command.create_response(&ctx.http, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(message))).await
+        };
+        if let Err(why) = result {
+            println!("Error sending message: {why:?}");
+            return false;
+        }
+        return true;
+    }
+
+    ///Answers the command with an embed. Returns whether the message got sent.
+    pub async fn reply_embed(&self, ctx: &Context, embed: CreateEmbed) -> bool {
+        return self.reply_embed_with_components(ctx, embed, vec![]).await;
+    }
+
+    ///Answers the command with an embed and buttons under it. Returns whether the message got sent.
+    pub async fn reply_embed_with_components(&self, ctx: &Context, embed: CreateEmbed, components: Vec<CreateActionRow>) -> bool {
+        let result = match self {
+            Invocation::Message(msg) => msg.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed).components(components)).await.map(|_| ()),
+            Invocation::Slash(command) => command.create_response(&ctx.http, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().embed(embed).components(components))).await
+        };
+        if let Err(why) = result {
+            println!("Error sending message: {why:?}");
+            return false;
+        }
+        return true;
+    }
+
+    ///Answers the command with a file, like a rendered image. Returns whether the message got sent.
+    pub async fn reply_file(&self, ctx: &Context, file: CreateAttachment) -> bool {
+        let result = match self {
+            Invocation::Message(msg) => msg.channel_id.send_message(&ctx.http, CreateMessage::new().add_file(file)).await.map(|_| ()),
+            Invocation::Slash(command) => command.create_response(&ctx.http, CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().add_file(file))).await
+        };
+        if let Err(why) = result {
+            println!("Error sending message: {why:?}");
+            return false;
+        }
+        return true;
+    }
+
+    ///Sends more text after the command was answered, for commands that report back once slow work is done.
+    ///Returns whether the message got sent.
+    pub async fn follow_up(&self, ctx: &Context, message: String) -> bool {
+        let result = match self {
+            Invocation::Message(msg) => msg.channel_id.say(&ctx.http, message).await.map(|_| ()),
+            Invocation::Slash(command) => command.create_followup(&ctx.http, CreateInteractionResponseFollowup::new().content(message)).await.map(|_| ())
+        };
+        if let Err(why) = result {
+            println!("Error sending message: {why:?}");
+            return false;
+        }
+        return true;
+    }
+
+    ///Answers a command whose work can take longer than Discord waits for a slash command's answer. Slash
+    ///commands are deferred so they show the bot thinking, `!` commands get `note` right away, and then
+    ///the outcome of `work` is sent as a follow up, errors included. Returns whether the outcome got sent.
+    pub async fn reply_after(&self, ctx: &Context, note: String, work: impl Future<Output = Result<String, CommandError>>) -> bool {
+        let acknowledged = match self {
+            Invocation::Message(msg) => msg.channel_id.say(&ctx.http, note).await.map(|_| ()),
+            Invocation::Slash(command) => command.defer(&ctx.http).await
+        };
+        if let Err(why) = acknowledged {
+            println!("Error acknowledging command: {why:?}");
+        }
+        let out = match work.await {
+            Ok(out) => out,
+            Err(why) => {
+                if let CommandError::Discord(why) = &why {
+                    println!("Error running command: {why:?}");
+                }
+                why.to_string()
+            }
+        };
+        return self.follow_up(ctx, out).await;
+    }
+}
@@ -1,56 +1,114 @@
-use std::{env, fs::File, io::Read, sync::Arc};
+use std::{env, path::Path, sync::Arc};
 use dotenv::dotenv;
 
 use serenity::prelude::*;
 
+mod announcements;
+mod card;
 mod data;
 mod handler;
 mod commands;
+mod framework;
 mod commandlib;
+mod error;
+mod invocation;
+mod ranks;
+mod reconcile;
+mod roles;
+mod storage;
+mod migrations;
 mod lemon;
+mod tokenizer;
+mod weekly;
+mod xp;
 
 use crate::data::*;
+use crate::ranks::*;
+use crate::storage::*;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    // Load the level curve, RANKS_FILE is the default and RANKS_DIR holds `<guild id>.json` overrides
+    let ranks = match RankTables::load(Path::new(&env::var("RANKS_FILE").unwrap_or("ranks.json".to_string())), Path::new(&env::var("RANKS_DIR").unwrap_or("ranks".to_string()))) {
+        Ok(ranks) => Arc::new(ranks),
+        Err(why) => {
+            println!("error loading ranks: {}", why);
+            return;
+        }
+    };
+    // `discordbot import` copies stats.txt into the sqlite database and exits
+    if env::args().nth(1).as_deref() == Some("import") {
+        let imported = SqliteStorage::from_env().and_then(|sqlite| import_json(&JsonStorage::from_env(), &sqlite, &ranks));
+        if let Err(why) = imported {
+            println!("import failed: {}", why);
+        }
+        return;
+    }
     // Configure the client with your Discord bot token in the environment.
     let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
-    // Set gateway intents, which decides what events the bot will be notified about
-    let intents = GatewayIntents::GUILD_MESSAGES
+    // Set gateway intents, which decides what events the bot will be notified about. GUILDS keeps
+    // the cache's copy of each guild and its roles up to date. Listing members for syncroles,
+    // purgebots and claimmigrated also needs the privileged Server Members intent turned on in the
+    // developer portal. It isn't requested here since connecting with it fails when it's turned off.
+    let intents = GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT;
 
-    // Try to load data from backup file
-    let data;
-    if let Ok(mut file) = File::open("stats.txt") {
-        let mut contents = String::new();
-        if let Ok(_) = file.read_to_string(&mut contents) {
-            if let Ok(d) = serde_json::from_str(&contents) {
-                data = d;
-            } else {
-                println!("error parsing json: {:?}", serde_json::from_str::<Data>(&contents));
-                return;
-            }
-        } else {
-            println!("error reading file");
+    // Load from the configured storage, for json that's stats.txt or the newest backup of it that still parses
+    let storage = match storage_from_env() {
+        Ok(storage) => storage,
+        Err(why) => {
+            println!("error opening storage: {}", why);
             return;
         }
-    } else {
-        println!("error opening file");
-        return;
-    }
+    };
+    // Nothing saved yet means a first run, but anything unreadable stops the bot so it never gets overwritten
+    let mut data = match storage.load() {
+        Ok(data) => data,
+        Err(StorageError::Missing(what)) => {
+            let backfill_days = env::var("FIRST_RUN_BACKFILL_DAYS").ok().and_then(|days| days.parse().ok()).unwrap_or(0);
+            println!("{} doesn't exist, starting fresh with {} days of history", what, backfill_days);
+            Data::first_run(backfill_days)
+        },
+        Err(why) => {
+            println!("{}", why);
+            println!("refusing to start with unreadable data, fix or remove it first");
+            return;
+        }
+    };
+
+    // Users migrated from the old global ledger go to LEGACY_GUILD_ID if it's set, otherwise
+    // they wait in `unassigned` until `ready` can tell which guild they belong to.
+    data.assign_unassigned_from_env(&ranks);
 
     // Create a new instance of the Client, logging in as a bot. This will automatically prepend
     // your bot token with "Bot ", which is a requirement by Discord for bot users.
     let mut client =
         Client::builder(&token, intents).event_handler(DiscordHandler).await.expect("Err creating client");
 
+    let data_lock = Arc::new(RwLock::new(data));
     {
         let mut clientdata = client.data.write().await;
-        clientdata.insert::<GlobalData>(Arc::new(RwLock::new(data)));
+        clientdata.insert::<GlobalData>(data_lock.clone());
+        clientdata.insert::<RankData>(ranks.clone());
     }
 
+    // Save in the background, and stop the shards on SIGINT/SIGTERM so the final save below runs
+    let saver = Arc::new(Saver::from_env(data_lock.clone(), storage));
+    tokio::spawn(saver.clone().run());
+    // Post each guild's weekly top members when a week ends
+    tokio::spawn(weekly::run(client.http.clone(), data_lock.clone()));
+    // Fix rank roles that drifted while the bot was down or missing permissions
+    tokio::spawn(reconcile::run(client.cache.clone(), client.http.clone(), data_lock, ranks));
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        wait_for_shutdown().await;
+        println!("shutting down");
+        shard_manager.shutdown_all().await;
+    });
+
     // Finally, start a single shard, and start listening to events.
     //
     // Shards will automatically attempt to reconnect, and will perform exponential backoff until
@@ -58,4 +116,22 @@ async fn main() {
     if let Err(why) = client.start().await {
         println!("Client error: {why:?}");
     }
-}
\ No newline at end of file
+
+    saver.flush().await;
+}
+
+///Waits for ctrl+c, or SIGTERM on unix
+async fn wait_for_shutdown() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).expect("Err registering SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+///The version of the stats document this build writes
+pub const CURRENT_VERSION: u64 = 1;
+
+type Migration = fn(Map<String, Value>) -> Result<Map<String, Value>, MigrationError>;
+
+///Each step upgrades a document from version `index` to `index + 1`
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [
+    v0_to_v1
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    NotAnObject,
+    ///The document was written by a newer build than this one
+    TooNew(u64),
+    Invalid { version: u64, reason: String }
+}
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::NotAnObject => write!(f, "stats document isn't a json object"),
+            MigrationError::TooNew(version) => write!(f, "stats document is version {} but this build only understands up to {}", version, CURRENT_VERSION),
+            MigrationError::Invalid { version, reason } => write!(f, "can't upgrade version {} stats document: {}", version, reason)
+        }
+    }
+}
+
+///Works out which version a document is, the only layout without a version is the original one
+pub fn detect_version(document: &Map<String, Value>) -> u64 {
+    return document.get("version").and_then(|version| version.as_u64()).unwrap_or(0);
+}
+
+///Upgrades a document one version at a time until it's current. Returns it with the version it started at.
+pub fn migrate(document: Value) -> Result<(Value, u64), MigrationError> {
+    let Value::Object(mut document) = document else {
+        return Err(MigrationError::NotAnObject);
+    };
+    let original = detect_version(&document);
+    if original > CURRENT_VERSION {
+        return Err(MigrationError::TooNew(original));
+    }
+    for version in original..CURRENT_VERSION {
+        println!("upgrading stats document from version {} to {}", version, version + 1);
+        document = MIGRATIONS[version as usize](document)?;
+    }
+    return Ok((Value::Object(document), original));
+}
+
+///v0 kept one global map of users under `data`. v1 splits users into per-guild ledgers,
+///and since v0 doesn't say which guild anyone was in they wait in `unassigned`.
+fn v0_to_v1(mut document: Map<String, Value>) -> Result<Map<String, Value>, MigrationError> {
+    let Some(users) = document.remove("data") else {
+        return Err(MigrationError::Invalid { version: 0, reason: "missing data".to_string() });
+    };
+    document.remove("changed");
+    document.insert("version".to_string(), Value::from(1));
+    document.insert("guilds".to_string(), Value::Object(Map::new()));
+    document.insert("unassigned".to_string(), users);
+    return Ok(document);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Data;
+
+    const V0: &str = include_str!("fixtures/stats_v0.json");
+    const V1: &str = include_str!("fixtures/stats_v1.json");
+
+    #[test]
+    fn detects_versions() {
+        for (fixture, version) in [(V0, 0), (V1, 1)] {
+            let Value::Object(document) = serde_json::from_str(fixture).unwrap() else { panic!() };
+            assert_eq!(detect_version(&document), version);
+        }
+    }
+
+    #[test]
+    fn upgrades_v0() {
+        let data = Data::from_json(V0).unwrap();
+        assert!(data.guilds.is_empty());
+        assert_eq!(data.unassigned.len(), 2);
+        assert_eq!(data.unassigned[&111].xp, 57);
+        assert_eq!(data.unassigned[&222].user_data["color"], "blue");
+        assert!(data.changed());
+    }
+
+    #[test]
+    fn loads_current_version_unchanged() {
+        let data = Data::from_json(V1).unwrap();
+        assert_eq!(data.guilds.len(), 2);
+        assert_eq!(data.guilds[&2000].users[&111].xp, 3);
+        assert_eq!(data.guilds[&1000].users[&222].level, 2);
+        assert!(!data.changed());
+        let (document, version) = migrate(serde_json::from_str(V1).unwrap()).unwrap();
+        assert_eq!(version, CURRENT_VERSION);
+        assert_eq!(document, serde_json::from_str::<Value>(V1).unwrap());
+    }
+
+    #[test]
+    fn every_version_ends_up_the_same() {
+        let mut v0 = Data::from_json(V0).unwrap();
+        v0.assign_unassigned(1000, &crate::ranks::RankTable::default());
+        let v1 = Data::from_json(V1).unwrap();
+        assert_eq!(v0.guilds[&1000].users[&111].xp, v1.guilds[&1000].users[&111].xp);
+        assert_eq!(v0.guilds[&1000].users[&222].user_data, v1.guilds[&1000].users[&222].user_data);
+    }
+
+    #[test]
+    fn saved_documents_are_current() {
+        let data = Data::from_json(V0).unwrap();
+        let document: Value = serde_json::to_value(&data).unwrap();
+        assert_eq!(document["version"], CURRENT_VERSION);
+        assert!(document.get("changed").is_none());
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        assert!(matches!(migrate(serde_json::json!({"version": CURRENT_VERSION + 1, "guilds": {}})), Err(MigrationError::TooNew(_))));
+        assert!(matches!(migrate(serde_json::json!([])), Err(MigrationError::NotAnObject)));
+    }
+}
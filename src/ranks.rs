@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, fs, path::Path, sync::Arc};
+
+use serenity::prelude::*;
+
+pub struct RankData;
+impl TypeMapKey for RankData {
+    type Value = Arc<RankTables>;
+}
+
+///One step of the level curve, reached once a user has `threshold` xp
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RankDefinition {
+    pub threshold: u64,
+    pub name: String,
+    ///Name of the discord role for this rank, defaults to the rank name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    ///ANSI color code used when printing users of this rank
+    pub color: String,
+    ///Text sent when someone reaches this rank instead of the default message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announcement: Option<String>
+}
+
+#[derive(Debug)]
+pub enum RankError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    Empty,
+    NotMonotonic { index: usize, threshold: u64, previous: u64 },
+    MissingName { index: usize }
+}
+impl fmt::Display for RankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RankError::Io(why) => write!(f, "couldn't read rank file: {}", why),
+            RankError::Parse(why) => write!(f, "couldn't parse rank file: {}", why),
+            RankError::Empty => write!(f, "rank file has no ranks"),
+            RankError::NotMonotonic { index, threshold, previous } => write!(f, "rank {} has threshold {} which isn't above the previous threshold {}", index + 1, threshold, previous),
+            RankError::MissingName { index } => write!(f, "rank {} has no name", index + 1)
+        }
+    }
+}
+
+///The level curve and rank names, level N is reached at the Nth threshold
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RankTable {
+    pub ranks: Vec<RankDefinition>
+}
+impl RankTable {
+    ///Parses and validates a rank file's contents
+    pub fn from_json(contents: &str) -> Result<RankTable, RankError> {
+        let table: RankTable = serde_json::from_str(contents).map_err(RankError::Parse)?;
+        table.validate()?;
+        return Ok(table);
+    }
+
+    pub fn load(path: &Path) -> Result<RankTable, RankError> {
+        return RankTable::from_json(&fs::read_to_string(path).map_err(RankError::Io)?);
+    }
+
+    ///Checks that there is at least one rank, every rank is named, and thresholds strictly increase
+    pub fn validate(&self) -> Result<(), RankError> {
+        if self.ranks.is_empty() {
+            return Err(RankError::Empty);
+        }
+        for (index, rank) in self.ranks.iter().enumerate() {
+            if rank.name.trim().is_empty() {
+                return Err(RankError::MissingName { index });
+            }
+            if index > 0 && rank.threshold <= self.ranks[index - 1].threshold {
+                return Err(RankError::NotMonotonic { index, threshold: rank.threshold, previous: self.ranks[index - 1].threshold });
+            }
+        }
+        return Ok(());
+    }
+
+    ///Gets a level from an amount of xp
+    pub fn level(&self, xp: u64) -> u64 {
+        return self.ranks.iter().filter(|rank| xp >= rank.threshold).count() as u64;
+    }
+
+    ///Gets the definition for a level, levels past the end use the final rank
+    pub fn definition(&self, level: u64) -> Option<&RankDefinition> {
+        if level == 0 {
+            return None;
+        }
+        return self.ranks.get((level as usize).min(self.ranks.len()) - 1);
+    }
+
+    ///Gets a rank's name for a level, level 0 has no rank
+    pub fn rank(&self, level: u64) -> String {
+        return self.definition(level).map(|rank| rank.name.clone()).unwrap_or_default();
+    }
+
+    ///Gets the name of the role given at a level
+    pub fn role(&self, level: u64) -> String {
+        return self.definition(level).map(|rank| rank.role.clone().unwrap_or(rank.name.clone())).unwrap_or_default();
+    }
+
+    pub fn color(&self, level: u64) -> String {
+        return self.definition(level).map(|rank| rank.color.clone()).unwrap_or("37".to_string());
+    }
+
+    pub fn announcement(&self, level: u64) -> Option<String> {
+        return self.definition(level).and_then(|rank| rank.announcement.clone());
+    }
+
+    ///The rank color as RGB, for places that can't show ANSI like embeds and images
+    pub fn rgb(&self, level: u64) -> (u8, u8, u8) {
+        return ansi_rgb(&self.color(level));
+    }
+
+    pub fn thresholds(&self) -> Vec<u64> {
+        return self.ranks.iter().map(|rank| rank.threshold).collect();
+    }
+
+    ///Where the current level started and where the next one starts, `None` once the last rank is reached
+    pub fn progress(&self, xp: u64) -> (u64, Option<u64>) {
+        let level = self.level(xp) as usize;
+        let start = if level == 0 {0} else {self.ranks[level - 1].threshold};
+        return (start, self.ranks.get(level).map(|rank| rank.threshold));
+    }
+}
+
+///Maps an ANSI SGR code like `40;33` to the RGB Discord renders it as. The foreground color wins,
+///a code with only a background uses that, and anything unrecognised is white.
+pub fn ansi_rgb(code: &str) -> (u8, u8, u8) {
+    const FOREGROUND: [(u8, u8, u8); 8] = [(0x4f, 0x54, 0x5c), (0xdc, 0x32, 0x2f), (0x85, 0x99, 0x00), (0xb5, 0x89, 0x00), (0x26, 0x8b, 0xd2), (0xd3, 0x36, 0x82), (0x2a, 0xa1, 0x98), (0xff, 0xff, 0xff)];
+    const BACKGROUND: [(u8, u8, u8); 8] = [(0x00, 0x2b, 0x36), (0xcb, 0x4b, 0x16), (0x58, 0x6e, 0x75), (0x65, 0x7b, 0x83), (0x83, 0x94, 0x96), (0x6c, 0x71, 0xc4), (0x93, 0xa1, 0xa1), (0xfd, 0xf6, 0xe3)];
+    let codes: Vec<usize> = code.split(';').filter_map(|part| part.trim().parse().ok()).collect();
+    if let Some(foreground) = codes.iter().find(|code| (30..38).contains(*code)) {
+        return FOREGROUND[foreground - 30];
+    }
+    if let Some(background) = codes.iter().find(|code| (40..48).contains(*code)) {
+        return BACKGROUND[background - 40];
+    }
+    return FOREGROUND[7];
+}
+impl Default for RankTable {
+    fn default() -> RankTable {
+        let ranks = [
+            (10, "Beginner - Rank I", "36"),
+            (50, "Novice - Rank II", "32"),
+            (200, "Expert - Rank III", "42;32"),
+            (500, "Master - Rank IV", "40;33"),
+            (1000, "Sage - Rank V", "41;37"),
+            (2000, "Legend - Rank VI", "40;31"),
+            (3250, "Myth - Rank VII", "47;32"),
+            (5000, "Lord - Rank VIII", "40;34"),
+            (7000, "Wizard - Rank IX", "45;37"),
+            (10000, "Ghost - Rank X", "42;30"),
+            (13500, "Demon - Rank XI", "41;30"),
+            (17500, "No Life - Rank XII", "47;36"),
+            (22000, "Grinder - Rank XIII", "42;35"),
+            (27500, "Farmer - Rank XIV", "45;30"),
+            (35000, "Destroyer - Rank XV", "40;32"),
+            (45000, "Obliterator - Rank XVI", "47;35"),
+            (57500, "Millionaire - Rank XVII", "42;33"),
+            (70000, "Billionaire - Rank XVIII", "42;34"),
+            (85000, "Ascendant - Rank XIX", "42;37"),
+            (105000, "Overflowing - Rank XX", "40;35"),
+            (130000, "Eternal - Rank XXI", "47;33")
+        ];
+        return RankTable {
+            ranks: ranks.iter().map(|(threshold, name, color)| RankDefinition { threshold: *threshold, name: name.to_string(), role: None, color: color.to_string(), announcement: None }).collect()
+        };
+    }
+}
+
+///The default rank table plus any guild specific overrides
+#[derive(Clone, Debug, Default)]
+pub struct RankTables {
+    pub default: Arc<RankTable>,
+    pub guilds: HashMap<u64, Arc<RankTable>>
+}
+impl RankTables {
+    ///Loads `default_path` (or the built in table if it doesn't exist) and every `<guild id>.json` in `guild_dir`
+    pub fn load(default_path: &Path, guild_dir: &Path) -> Result<RankTables, RankError> {
+        let default = if default_path.exists() {
+            println!("loading ranks from {}", default_path.display());
+            RankTable::load(default_path)?
+        } else {
+            RankTable::default()
+        };
+        let mut guilds = HashMap::new();
+        if let Ok(entries) = fs::read_dir(guild_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    if let Some(guild_id) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok()) {
+                        println!("loading ranks for guild {} from {}", guild_id, path.display());
+                        guilds.insert(guild_id, Arc::new(RankTable::load(&path)?));
+                    }
+                }
+            }
+        }
+        return Ok(RankTables { default: Arc::new(default), guilds });
+    }
+
+    ///Gets the rank table a guild uses
+    pub fn for_guild(&self, guild_id: u64) -> Arc<RankTable> {
+        return self.guilds.get(&guild_id).unwrap_or(&self.default).clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(thresholds: &[u64]) -> RankTable {
+        return RankTable {
+            ranks: thresholds.iter().enumerate().map(|(i, threshold)| RankDefinition { threshold: *threshold, name: format!("Rank {}", i + 1), role: None, color: format!("3{}", i + 1), announcement: None }).collect()
+        };
+    }
+
+    #[test]
+    fn level_below_first_threshold_is_zero() {
+        let ranks = table(&[10, 50, 200]);
+        assert_eq!(ranks.level(0), 0);
+        assert_eq!(ranks.level(9), 0);
+        assert_eq!(ranks.rank(0), "");
+        assert_eq!(ranks.color(0), "37");
+    }
+
+    #[test]
+    fn xp_exactly_on_threshold_reaches_level() {
+        let ranks = table(&[10, 50, 200]);
+        assert_eq!(ranks.level(10), 1);
+        assert_eq!(ranks.level(49), 1);
+        assert_eq!(ranks.level(50), 2);
+        assert_eq!(ranks.level(200), 3);
+        assert_eq!(ranks.rank(2), "Rank 2");
+    }
+
+    #[test]
+    fn xp_beyond_final_rank_stays_on_final_rank() {
+        let ranks = table(&[10, 50, 200]);
+        assert_eq!(ranks.level(u64::MAX), 3);
+        assert_eq!(ranks.rank(3), "Rank 3");
+        assert_eq!(ranks.rank(99), "Rank 3");
+        assert_eq!(ranks.color(99), "33");
+    }
+
+    #[test]
+    fn progress_spans_the_current_level() {
+        let ranks = table(&[10, 50, 200]);
+        assert_eq!(ranks.progress(0), (0, Some(10)));
+        assert_eq!(ranks.progress(10), (10, Some(50)));
+        assert_eq!(ranks.progress(199), (50, Some(200)));
+        assert_eq!(ranks.progress(5000), (200, None));
+    }
+
+    #[test]
+    fn ansi_colors_map_to_rgb() {
+        assert_eq!(ansi_rgb("31"), (0xdc, 0x32, 0x2f));
+        assert_eq!(ansi_rgb("40;33"), (0xb5, 0x89, 0x00));
+        assert_eq!(ansi_rgb("1;36"), (0x2a, 0xa1, 0x98));
+        assert_eq!(ansi_rgb("45"), (0x6c, 0x71, 0xc4));
+        assert_eq!(ansi_rgb("nonsense"), (0xff, 0xff, 0xff));
+        assert_eq!(RankTable::default().rgb(0), (0xff, 0xff, 0xff));
+    }
+
+    #[test]
+    fn default_table_matches_builtin_curve() {
+        let ranks = RankTable::default();
+        assert!(ranks.validate().is_ok());
+        assert_eq!(ranks.thresholds().len(), 21);
+        assert_eq!(ranks.level(9), 0);
+        assert_eq!(ranks.level(10), 1);
+        assert_eq!(ranks.level(130000), 21);
+        assert_eq!(ranks.rank(21), "Eternal - Rank XXI");
+        assert_eq!(ranks.color(4), "40;33");
+        assert_eq!(ranks.role(5), "Sage - Rank V");
+    }
+
+    #[test]
+    fn parses_optional_fields() {
+        let ranks = RankTable::from_json(r#"{"ranks": [
+            {"threshold": 5, "name": "Fresh", "color": "36"},
+            {"threshold": 20, "name": "Regular", "role": "regulars", "color": "32", "announcement": "{user} is a regular now"}
+        ]}"#).unwrap();
+        assert_eq!(ranks.role(1), "Fresh");
+        assert_eq!(ranks.role(2), "regulars");
+        assert_eq!(ranks.announcement(1), None);
+        assert_eq!(ranks.announcement(2), Some("{user} is a regular now".to_string()));
+    }
+
+    #[test]
+    fn rejects_non_increasing_thresholds() {
+        assert!(matches!(table(&[10, 50, 50]).validate(), Err(RankError::NotMonotonic { index: 2, .. })));
+        assert!(matches!(table(&[10, 5]).validate(), Err(RankError::NotMonotonic { index: 1, .. })));
+        assert!(matches!(table(&[]).validate(), Err(RankError::Empty)));
+        assert!(matches!(RankTable::from_json(r#"{"ranks": [{"threshold": 1, "name": " ", "color": "36"}]}"#), Err(RankError::MissingName { index: 0 })));
+    }
+
+    #[test]
+    fn guilds_without_a_file_use_the_default() {
+        let mut tables = RankTables::default();
+        tables.guilds.insert(1, Arc::new(table(&[1, 2])));
+        assert_eq!(tables.for_guild(1).level(2), 2);
+        assert_eq!(tables.for_guild(2).level(2), 0);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{collections::{BTreeSet, HashMap}, sync::{Arc, Mutex}, time::Duration};
+
+use serenity::all::{Cache, CacheHttp, GuildId, Http, UserId};
+use serenity::prelude::*;
+
+use crate::commandlib::get_all_members;
+use crate::data::*;
+use crate::error::CommandError;
+use crate::ranks::{RankTable, RankTables};
+use crate::roles::{RankRoles, RoleChanges};
+
+///How often every guild's rank roles get checked in the background
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+///How long after starting the first background check waits, so catching up on history goes first
+const STARTUP_DELAY: Duration = Duration::from_secs(3600);
+///Members fixed in one go before pausing, so live rank ups aren't stuck behind a long queue of role requests
+const BATCH_SIZE: usize = 10;
+///Pause between batches
+const BATCH_PAUSE: Duration = Duration::from_secs(2);
+///Failures in a row after which the bot most likely can't manage the roles at all, so it stops trying
+const MAX_FAILURES_IN_A_ROW: usize = 5;
+
+///Guilds being reconciled right now, so an admin and the background job never work on one at the same time
+static RUNNING: Mutex<BTreeSet<u64>> = Mutex::new(BTreeSet::new());
+
+///Marks a guild as being reconciled until dropped
+struct Running(u64);
+impl Running {
+    fn start(guild_id: u64) -> Option<Running> {
+        if !RUNNING.lock().unwrap().insert(guild_id) {
+            return None;
+        }
+        return Some(Running(guild_id));
+    }
+}
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().remove(&self.0);
+    }
+}
+
+///What a reconciliation found and did
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReconcileReport {
+    ///Members looked at
+    pub checked: usize,
+    ///Recorded users who aren't in the server anymore
+    pub absent: usize,
+    ///Members without a record, whose roles are left alone
+    pub unrecorded: usize,
+    ///Stored levels that didn't match the user's XP
+    pub levels: usize,
+    ///Members whose roles were wrong
+    pub drifted: usize,
+    pub added: usize,
+    pub removed: usize,
+    ///Members whose roles couldn't all be changed
+    pub failed: usize,
+    ///Whether it gave up after too many failures in a row
+    pub stopped: bool
+}
+impl ReconcileReport {
+    ///A few lines for whoever asked, `dry_run` reports what would have changed
+    pub fn summary(&self, dry_run: bool) -> String {
+        let mut out = format!("Checked {} members", self.checked);
+        if self.absent > 0 {
+            out += &format!(", {} recorded users have left", self.absent);
+        }
+        if self.unrecorded > 0 {
+            out += &format!(", {} members without a record were left alone", self.unrecorded);
+        }
+        out += ".\n";
+        if self.drifted == 0 && self.levels == 0 {
+            return out + "Everyone's rank roles were already right.";
+        }
+        let verb = if dry_run {"would be"} else {"were"};
+        if self.levels > 0 {
+            out += &format!("{} stored levels {} corrected.\n", self.levels, verb);
+        }
+        if self.drifted > 0 {
+            out += &format!("{} members had the wrong roles, {} roles {} added and {} removed.", self.drifted, self.added, verb, self.removed);
+        }
+        if self.failed > 0 {
+            out += &format!("\n{} members couldn't be fixed, check the bot has Manage Roles and its role is above the rank roles.", self.failed);
+        }
+        if self.stopped {
+            out += "\nStopped early after too many failures in a row.";
+        }
+        return out;
+    }
+}
+
+///Works out the role changes each member needs. Members without a record are left alone, their rank roles
+///may have been given by hand or belong to XP that hasn't been assigned to this guild yet.
+pub fn plan(rank_roles: &RankRoles, levels: &HashMap<u64, u64>, members: &[(u64, Vec<u64>)]) -> Vec<(u64, RoleChanges)> {
+    return members.iter()
+        .filter_map(|(user_id, roles)| Some((*user_id, rank_roles.changes(*levels.get(user_id)?, roles))))
+        .filter(|(_, changes)| !changes.is_empty())
+        .collect();
+}
+
+///Brings every member's rank roles in a guild in line with their level. Stored levels that don't match a
+///user's XP are corrected first, without announcing anything. With `dry_run` nothing gets changed.
+pub async fn reconcile_guild(cache_http: &impl CacheHttp, data: &RwLock<Data>, ranks: &RankTable, guild_id: GuildId, dry_run: bool) -> Result<ReconcileReport, CommandError> {
+    let Some(_running) = Running::start(guild_id.into()) else {
+        return Err(CommandError::Invalid("Rank roles are already being checked here, wait for that to finish".to_string()));
+    };
+    let http = cache_http.http();
+    let members: Vec<(u64, Vec<u64>)> = get_all_members(http, guild_id).await?.into_iter()
+        .filter(|member| !member.user.bot)
+        .map(|member| (u64::from(member.user.id), member.roles.iter().map(|role_id| u64::from(*role_id)).collect()))
+        .collect();
+    let mut report = ReconcileReport { checked: members.len(), ..Default::default() };
+
+    let (settings, levels) = {
+        let mut data = data.write().await;
+        let ledger = data.guild_mut(guild_id.into());
+        let mut levels = HashMap::new();
+        for user in ledger.users.values_mut() {
+            let level = ranks.level(user.xp);
+            if level != user.level {
+                report.levels += 1;
+                if !dry_run {
+                    user.level = level;
+                }
+            }
+            levels.insert(user.id, level);
+        }
+        let settings = ledger.role_settings();
+        if report.levels > 0 && !dry_run {
+            data.mark_ledger_changed(guild_id.into());
+        }
+        (settings, levels)
+    };
+    let present: BTreeSet<u64> = members.iter().map(|(user_id, _)| *user_id).collect();
+    report.absent = levels.keys().filter(|user_id| !present.contains(user_id)).count();
+    report.unrecorded = members.iter().filter(|(user_id, _)| !levels.contains_key(user_id)).count();
+
+    let rank_roles = RankRoles::load(cache_http, guild_id, &settings, ranks).await;
+    let fixes = plan(&rank_roles, &levels, &members);
+    report.drifted = fixes.len();
+    report.added = fixes.iter().map(|(_, changes)| changes.add.len()).sum();
+    report.removed = fixes.iter().map(|(_, changes)| changes.remove.len()).sum();
+    if dry_run {
+        return Ok(report);
+    }
+
+    let mut failures_in_a_row = 0;
+    for (index, (user_id, changes)) in fixes.iter().enumerate() {
+        if index > 0 && index % BATCH_SIZE == 0 {
+            tokio::time::sleep(BATCH_PAUSE).await;
+        }
+        if changes.apply(http, guild_id, UserId::new(*user_id), "Rank roles reconciled").await {
+            failures_in_a_row = 0;
+        } else {
+            report.failed += 1;
+            failures_in_a_row += 1;
+            if failures_in_a_row >= MAX_FAILURES_IN_A_ROW {
+                report.stopped = true;
+                break;
+            }
+        }
+    }
+    return Ok(report);
+}
+
+///Reconciles every guild's rank roles once a day. Without the Server Members intent every guild fails and
+///says so in the log, nothing else is affected.
+pub async fn run(cache: Arc<Cache>, http: Arc<Http>, data: Arc<RwLock<Data>>, ranks: Arc<RankTables>) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + STARTUP_DELAY, RECONCILE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let guild_ids: Vec<u64> = data.read().await.guilds.keys().copied().collect();
+        for guild_id in guild_ids {
+            match reconcile_guild(&(&cache, &*http), &data, &ranks.for_guild(guild_id), GuildId::new(guild_id), false).await {
+                Ok(report) => println!("reconciled rank roles in {}: {}", guild_id, report.summary(false).replace('\n', " ")),
+                Err(why) => println!("couldn't reconcile rank roles in {}: {}", guild_id, why)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use serenity::all::RoleId;
+
+    #[test]
+    fn plans_fixes_for_drifted_members_only() {
+        let rank_roles = RankRoles { by_level: BTreeMap::from([(1, RoleId::new(1)), (2, RoleId::new(2))]), ..Default::default() };
+        let levels = HashMap::from([(10, 2), (11, 1)]);
+        let members = vec![(10, vec![1, 50]), (11, vec![1]), (12, vec![2])];
+        // 12 has no record, the role they have may have been given by hand
+        assert_eq!(plan(&rank_roles, &levels, &members), vec![
+            (10, RoleChanges { add: vec![RoleId::new(2)], remove: vec![RoleId::new(1)] })
+        ]);
+    }
+
+    #[test]
+    fn summarizes_what_changed() {
+        let clean = ReconcileReport { checked: 3, ..Default::default() };
+        assert_eq!(clean.summary(false), "Checked 3 members.\nEveryone's rank roles were already right.");
+        let report = ReconcileReport { checked: 40, absent: 2, unrecorded: 6, drifted: 5, added: 4, removed: 3, failed: 1, ..Default::default() };
+        let summary = report.summary(true);
+        assert!(summary.starts_with("Checked 40 members, 2 recorded users have left, 6 members without a record were left alone.\n"));
+        assert!(summary.contains("5 members had the wrong roles, 4 roles would be added and 3 removed."));
+        assert!(summary.contains("1 members couldn't be fixed"));
+    }
+
+    #[test]
+    fn only_one_reconciliation_per_guild() {
+        let first = Running::start(77).unwrap();
+        assert!(Running::start(77).is_none());
+        assert!(Running::start(78).is_some());
+        drop(first);
+        assert!(Running::start(77).is_some());
+    }
+}
@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use serenity::all::{CacheHttp, GuildId, Http, Role, RoleId, UserId};
+
+use crate::ranks::RankTable;
+
+///How rank roles are handed out as members level up
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoleMode {
+    ///Only the role of the member's rank, the previous one is taken away
+    #[default]
+    Replace,
+    ///Every rank role the member has reached
+    Stack,
+    ///Only the role of the member's rank, plus the cosmetic role of every rank reached
+    Highest
+}
+impl RoleMode {
+    pub const ALL: [RoleMode; 3] = [RoleMode::Replace, RoleMode::Stack, RoleMode::Highest];
+
+    pub fn is_default(&self) -> bool {
+        return *self == RoleMode::default();
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            RoleMode::Replace => "replace",
+            RoleMode::Stack => "stack",
+            RoleMode::Highest => "highest"
+        };
+    }
+
+    pub fn parse(name: &str) -> Option<RoleMode> {
+        return RoleMode::ALL.into_iter().find(|mode| mode.name().eq_ignore_ascii_case(name));
+    }
+
+    pub fn description(&self) -> &'static str {
+        return match self {
+            RoleMode::Replace => "members only keep the role of their current rank",
+            RoleMode::Stack => "members keep the role of every rank they've reached",
+            RoleMode::Highest => "members keep the role of their current rank and the cosmetic role of every rank they've reached"
+        };
+    }
+}
+
+///A guild's saved rank role settings, copied out of the ledger so they can be used without holding it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoleSettings {
+    pub mode: RoleMode,
+    ///Role ids bound to levels with `rankroles`
+    pub bound: BTreeMap<u64, u64>,
+    ///Cosmetic role ids by level, only handed out in `RoleMode::Highest`
+    pub cosmetic: BTreeMap<u64, u64>
+}
+
+///A guild's rank roles by level. Ranks bound with `rankroles` use the bound role as long as it exists,
+///the others fall back to the role named like the rank. Cosmetic roles have to be bound.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RankRoles {
+    pub mode: RoleMode,
+    pub by_level: BTreeMap<u64, RoleId>,
+    pub cosmetic: BTreeMap<u64, RoleId>
+}
+impl RankRoles {
+    ///Works out the rank roles from the roles a guild has
+    pub fn resolve(settings: &RoleSettings, roles: &HashMap<RoleId, Role>, ranks: &RankTable) -> RankRoles {
+        let mut by_level = BTreeMap::new();
+        for level in 1..=ranks.ranks.len() as u64 {
+            let role_id = settings.bound.get(&level).map(|id| RoleId::new(*id)).filter(|role_id| roles.contains_key(role_id))
+                // Lowest id so two roles with the same name always resolve the same way
+                .or_else(|| roles.values().filter(|role| role.name == ranks.role(level)).map(|role| role.id).min());
+            if let Some(role_id) = role_id {
+                by_level.insert(level, role_id);
+            }
+        }
+        let cosmetic = settings.cosmetic.iter().map(|(level, id)| (*level, RoleId::new(*id))).filter(|(_, role_id)| roles.contains_key(role_id)).collect();
+        return RankRoles { mode: settings.mode, by_level, cosmetic };
+    }
+
+    ///Works out the rank roles from the gateway cache, only asking Discord when the guild isn't cached yet
+    pub async fn load(cache_http: &impl CacheHttp, guild_id: GuildId, settings: &RoleSettings, ranks: &RankTable) -> RankRoles {
+        if let Some(rank_roles) = cache_http.cache().and_then(|cache| cache.guild(guild_id)).map(|guild| RankRoles::resolve(settings, &guild.roles, ranks)) {
+            return rank_roles;
+        }
+        return match cache_http.http().get_guild_roles(guild_id).await {
+            Ok(roles) => RankRoles::resolve(settings, &roles.into_iter().map(|role| (role.id, role)).collect(), ranks),
+            Err(why) => {
+                println!("Error getting roles of {}: {why:?}", guild_id);
+                RankRoles::default()
+            }
+        };
+    }
+
+    ///The role given at a level, if the guild has one
+    pub fn role(&self, level: u64) -> Option<RoleId> {
+        return self.by_level.get(&level).copied();
+    }
+
+    ///Every role a member at `level` should have in this guild's mode
+    pub fn wanted(&self, level: u64) -> Vec<RoleId> {
+        let reached = |roles: &BTreeMap<u64, RoleId>| roles.range(1..=level).map(|(_, role_id)| *role_id).collect::<Vec<RoleId>>();
+        let mut wanted = match self.mode {
+            RoleMode::Replace => self.role(level).into_iter().collect(),
+            RoleMode::Stack => reached(&self.by_level),
+            RoleMode::Highest => self.role(level).into_iter().chain(reached(&self.cosmetic)).collect()
+        };
+        wanted.sort();
+        wanted.dedup();
+        return wanted;
+    }
+
+    ///Which rank and cosmetic roles a member at `level` has to gain and lose
+    pub fn changes(&self, level: u64, member_roles: &[u64]) -> RoleChanges {
+        let has = |role_id: &RoleId| member_roles.contains(&u64::from(*role_id));
+        let wanted = self.wanted(level);
+        let mut remove: Vec<RoleId> = self.by_level.values().chain(self.cosmetic.values()).filter(|role_id| !wanted.contains(role_id) && has(role_id)).copied().collect();
+        remove.sort();
+        remove.dedup();
+        return RoleChanges { add: wanted.into_iter().filter(|role_id| !has(role_id)).collect(), remove };
+    }
+}
+
+///Rank roles to give and take away from one member
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoleChanges {
+    pub add: Vec<RoleId>,
+    pub remove: Vec<RoleId>
+}
+impl RoleChanges {
+    pub fn is_empty(&self) -> bool {
+        return self.add.is_empty() && self.remove.is_empty();
+    }
+
+    ///The roles a member has once these changes are made, given the roles they had
+    pub fn applied_to(&self, member_roles: &[u64]) -> Vec<u64> {
+        let mut roles: Vec<u64> = member_roles.iter().copied().filter(|role_id| !self.remove.contains(&RoleId::new(*role_id))).collect();
+        roles.extend(self.add.iter().map(|role_id| u64::from(*role_id)).filter(|role_id| !member_roles.contains(role_id)));
+        return roles;
+    }
+
+    ///Makes the changes on Discord, returning whether all of them went through
+    pub async fn apply(&self, http: &Http, guild_id: GuildId, user_id: UserId, reason: &str) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let mut ok = true;
+        for role_id in &self.remove {
+            if let Err(why) = http.remove_member_role(guild_id, user_id, *role_id, Some(reason)).await {
+                println!("Error removing role {} from {}: {why:?}", role_id, user_id);
+                ok = false;
+            }
+        }
+        for role_id in &self.add {
+            if let Err(why) = http.add_member_role(guild_id, user_id, *role_id, Some(reason)).await {
+                println!("Error adding role {} to {}: {why:?}", role_id, user_id);
+                ok = false;
+            }
+        }
+        return ok;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(id: u64, name: &str) -> (RoleId, Role) {
+        let mut role = Role::default();
+        role.id = RoleId::new(id);
+        role.name = name.to_string();
+        return (role.id, role);
+    }
+
+    fn ranks() -> RankTable {
+        return RankTable::from_json(r#"{"ranks": [
+            {"threshold": 0, "name": "Fresh", "color": "37"},
+            {"threshold": 20, "name": "Regular", "color": "32"},
+            {"threshold": 50, "name": "Elder", "color": "33"}
+        ]}"#).unwrap();
+    }
+
+    #[test]
+    fn applied_changes_give_the_roles_afterwards() {
+        let changes = RoleChanges { add: vec![RoleId::new(3), RoleId::new(1)], remove: vec![RoleId::new(2)] };
+        assert_eq!(changes.applied_to(&[1, 2, 7]), vec![1, 7, 3]);
+        assert_eq!(RoleChanges::default().applied_to(&[1, 2]), vec![1, 2]);
+    }
+
+    #[test]
+    fn bound_roles_win_and_survive_renames() {
+        let roles = HashMap::from([role(1, "Fresh"), role(2, "Renamed regulars"), role(3, "Regular"), role(4, "Fresh")]);
+        let settings = RoleSettings { bound: BTreeMap::from([(2, 2), (3, 99)]), cosmetic: BTreeMap::from([(1, 4), (2, 98)]), ..Default::default() };
+        let rank_roles = RankRoles::resolve(&settings, &roles, &ranks());
+        assert_eq!(rank_roles.role(1), Some(RoleId::new(1)));
+        assert_eq!(rank_roles.role(2), Some(RoleId::new(2)));
+        // Bound to a role that was deleted and nothing is called Elder
+        assert_eq!(rank_roles.role(3), None);
+        assert_eq!(rank_roles.cosmetic, BTreeMap::from([(1, RoleId::new(4))]));
+    }
+
+    #[test]
+    fn members_end_up_with_only_their_rank_role() {
+        let rank_roles = RankRoles { by_level: BTreeMap::from([(1, RoleId::new(1)), (2, RoleId::new(2)), (3, RoleId::new(3))]), ..Default::default() };
+        assert_eq!(rank_roles.changes(2, &[1, 7]), RoleChanges { add: vec![RoleId::new(2)], remove: vec![RoleId::new(1)] });
+        assert!(rank_roles.changes(2, &[2, 7]).is_empty());
+        assert_eq!(rank_roles.changes(0, &[3]), RoleChanges { add: vec![], remove: vec![RoleId::new(3)] });
+    }
+
+    #[test]
+    fn stacking_keeps_every_reached_rank() {
+        let rank_roles = RankRoles { mode: RoleMode::Stack, by_level: BTreeMap::from([(1, RoleId::new(1)), (2, RoleId::new(2)), (3, RoleId::new(3))]), ..Default::default() };
+        assert_eq!(rank_roles.changes(2, &[2, 3]), RoleChanges { add: vec![RoleId::new(1)], remove: vec![RoleId::new(3)] });
+        assert!(rank_roles.changes(3, &[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn highest_mode_stacks_cosmetic_roles() {
+        let rank_roles = RankRoles {
+            mode: RoleMode::Highest,
+            by_level: BTreeMap::from([(1, RoleId::new(1)), (2, RoleId::new(2)), (3, RoleId::new(3))]),
+            cosmetic: BTreeMap::from([(1, RoleId::new(11)), (3, RoleId::new(13))])
+        };
+        assert_eq!(rank_roles.wanted(3), vec![RoleId::new(3), RoleId::new(11), RoleId::new(13)]);
+        assert_eq!(rank_roles.changes(2, &[1, 11, 13]), RoleChanges { add: vec![RoleId::new(2)], remove: vec![RoleId::new(1), RoleId::new(13)] });
+        // Going back to replacing takes the cosmetic roles away too
+        let replace = RankRoles { mode: RoleMode::Replace, ..rank_roles };
+        assert_eq!(replace.changes(2, &[2, 11]), RoleChanges { add: vec![], remove: vec![RoleId::new(11)] });
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(RoleMode::parse("Stack"), Some(RoleMode::Stack));
+        assert_eq!(RoleMode::parse("highest"), Some(RoleMode::Highest));
+        assert_eq!(RoleMode::parse("all"), None);
+        assert_eq!(serde_json::to_string(&RoleMode::Highest).unwrap(), "\"highest\"");
+    }
+}
@@ -0,0 +1,178 @@
+use std::{collections::HashMap, env, fs::{self, File}, io::{self, Write}, path::{Path, PathBuf}, sync::Mutex};
+
+use serenity::all::Timestamp;
+
+use crate::data::*;
+use crate::storage::{ChangeSet, Storage, StorageError};
+
+///Keeps everything in one json file, stats.txt, with rotating backups of it. The file always has to be
+///rewritten whole, so this keeps its own copy of what was saved and applies each change set to that.
+#[derive(Debug)]
+pub struct JsonStorage {
+    pub path: PathBuf,
+    pub backup_dir: PathBuf,
+    ///How many backups to keep, 0 disables backups
+    pub backups: usize,
+    ///Minimum number of seconds between two backups
+    pub backup_interval: i64,
+    ///What the file holds, from loading it or from the first complete save
+    saved: Mutex<Option<Data>>
+}
+impl JsonStorage {
+    ///Reads STATS_FILE, BACKUP_DIR, BACKUP_COUNT and BACKUP_INTERVAL, falling back to defaults
+    pub fn from_env() -> JsonStorage {
+        return JsonStorage {
+            path: PathBuf::from(env::var("STATS_FILE").unwrap_or("stats.txt".to_string())),
+            backup_dir: PathBuf::from(env::var("BACKUP_DIR").unwrap_or("backups".to_string())),
+            backups: env::var("BACKUP_COUNT").ok().and_then(|count| count.parse().ok()).unwrap_or(5),
+            backup_interval: env::var("BACKUP_INTERVAL").ok().and_then(|secs| secs.parse().ok()).unwrap_or(3600),
+            saved: Mutex::new(None)
+        };
+    }
+
+    ///Loads stats.txt, falling back to the newest backup that parses. Returns the data and the file it came from.
+    ///If stats.txt is corrupt it gets moved aside first, so the next save can't overwrite it.
+    pub fn load_with_path(&self) -> Result<(Data, PathBuf), StorageError> {
+        let error = match load_file(&self.path) {
+            Ok(data) => return Ok((data, self.path.clone())),
+            Err(why) => why
+        };
+        let backups = self.list_backups();
+        if let StorageError::Io(why) = &error {
+            if why.kind() == io::ErrorKind::NotFound && backups.is_empty() {
+                return Err(StorageError::Missing(self.path.display().to_string()));
+            }
+        }
+        println!("couldn't load {}: {}", self.path.display(), error);
+        for backup in backups.into_iter().rev() {
+            match load_file(&backup) {
+                Ok(data) => {
+                    println!("loaded backup {} instead", backup.display());
+                    if self.path.exists() {
+                        self.quarantine()?;
+                    }
+                    return Ok((data, backup));
+                },
+                Err(why) => println!("couldn't load backup {}: {}", backup.display(), why)
+            }
+        }
+        return Err(error);
+    }
+
+    ///Renames a stats.txt that couldn't be loaded to `<file>.corrupt-<time>`
+    fn quarantine(&self) -> Result<(), StorageError> {
+        let mut corrupt_path = self.path.clone().into_os_string();
+        corrupt_path.push(format!(".corrupt-{}", Timestamp::now().unix_timestamp()));
+        fs::rename(&self.path, &corrupt_path).map_err(StorageError::Io)?;
+        println!("moved the unreadable {} to {}", self.path.display(), PathBuf::from(corrupt_path).display());
+        return Ok(());
+    }
+
+    ///Writes the data to a temporary file and renames it over stats.txt, so a crash never leaves a half written file.
+    ///The previous stats.txt is kept as a backup first if the newest backup is old enough.
+    fn write(&self, data: &Data) -> io::Result<()> {
+        let filedata = serde_json::to_string_pretty(data).map_err(io::Error::other)?;
+        if self.path.exists() {
+            if let Err(why) = self.backup() {
+                println!("backup failed: {}", why);
+            }
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(filedata.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        return Ok(());
+    }
+
+    ///Copies stats.txt into the backup folder and deletes the oldest backups past the limit
+    fn backup(&self) -> io::Result<()> {
+        if self.backups == 0 {
+            return Ok(());
+        }
+        let now = Timestamp::now().unix_timestamp();
+        let backups = self.list_backups();
+        if let Some(newest) = backups.last().and_then(|path| backup_time(path)) {
+            if now - newest < self.backup_interval {
+                return Ok(());
+            }
+        }
+        fs::create_dir_all(&self.backup_dir)?;
+        fs::copy(&self.path, self.backup_dir.join(format!("{}.{}.bak", self.file_name(), now)))?;
+        let backups = self.list_backups();
+        for old in backups.iter().take(backups.len().saturating_sub(self.backups)) {
+            fs::remove_file(old)?;
+        }
+        return Ok(());
+    }
+
+    ///Lists the backups of stats.txt, oldest first
+    fn list_backups(&self) -> Vec<PathBuf> {
+        let prefix = format!("{}.", self.file_name());
+        let mut backups: Vec<(i64, PathBuf)> = vec![];
+        if let Ok(entries) = fs::read_dir(&self.backup_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with(&prefix)) {
+                    if let Some(time) = backup_time(&path) {
+                        backups.push((time, path));
+                    }
+                }
+            }
+        }
+        backups.sort();
+        return backups.into_iter().map(|(_, path)| path).collect();
+    }
+
+    fn file_name(&self) -> String {
+        return self.path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or("stats.txt".to_string());
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load(&self) -> Result<Data, StorageError> {
+        let (data, path) = self.load_with_path()?;
+        println!("loaded data from {}", path.display());
+        *self.saved.lock().expect("json lock poisoned") = Some(data.clone());
+        return Ok(data);
+    }
+
+    fn save(&self, changes: &ChangeSet) -> Result<(), StorageError> {
+        let mut saved = self.saved.lock().expect("json lock poisoned");
+        if saved.is_none() && !changes.complete {
+            // Writing only the changes would replace whatever the file holds
+            return Err(StorageError::Io(io::Error::other("nothing was loaded to apply the changes to")));
+        }
+        let data = saved.get_or_insert_with(|| Data::from_data(HashMap::new(), HashMap::new(), changes.last_timestamp));
+        changes.apply(data);
+        return self.write(data).map_err(StorageError::Io);
+    }
+}
+
+fn load_file(path: &Path) -> Result<Data, StorageError> {
+    let contents = fs::read_to_string(path).map_err(StorageError::Io)?;
+    return Data::from_json(&contents);
+}
+
+///Gets the unix time out of a backup named `<file>.<time>.bak`
+fn backup_time(path: &Path) -> Option<i64> {
+    let name = path.file_name()?.to_str()?.strip_suffix(".bak")?;
+    return name.rsplit('.').next()?.parse().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::data;
+
+    #[test]
+    fn never_writes_changes_without_the_rest() {
+        let mut data = data();
+        data.mark_user_changed(10, 1);
+        assert!(JsonStorage::from_env().save(&ChangeSet::take(&mut data)).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, env, fmt, io, sync::Arc, time::Duration};
+
+use serenity::all::Timestamp;
+use serenity::prelude::*;
+use tokio::time::Instant;
+
+use crate::data::*;
+use crate::ranks::RankTables;
+use crate::migrations::MigrationError;
+
+mod json;
+mod sqlite;
+
+pub use json::JsonStorage;
+pub use sqlite::SqliteStorage;
+
+///Somewhere `Data` can be loaded from and saved to. Everything is read once at startup and served from memory
+///after that, commands and awards never wait on storage. Saves only carry what changed since the last one.
+pub trait Storage: Send + Sync {
+    ///Loads everything that was saved
+    fn load(&self) -> Result<Data, StorageError>;
+    ///Writes the changes, a complete change set replaces whatever was there
+    fn save(&self, changes: &ChangeSet) -> Result<(), StorageError>;
+}
+
+///What changed since the last save, copied out of `Data` so it can be written without holding the lock
+#[derive(Clone, Debug)]
+pub struct ChangeSet {
+    pub version: u64,
+    pub last_timestamp: Timestamp,
+    pub unassigned: HashMap<u64, UserData>,
+    ///Holds every guild and user, so anything stored that isn't in here is gone
+    pub complete: bool,
+    ///Guild settings that changed, the users of these are always empty
+    pub settings: HashMap<u64, GuildData>,
+    ///Guilds whose users are all written, stored users that aren't among them were removed
+    pub ledgers: HashMap<u64, Vec<UserData>>,
+    ///Single users that changed, `None` for users that were removed
+    pub users: HashMap<(u64, u64), Option<UserData>>,
+    ///What was marked to produce this, put back if saving fails
+    pub pending: Pending
+}
+impl ChangeSet {
+    ///Copies everything, for imports and the first save
+    pub fn all(data: &Data) -> ChangeSet {
+        let mut changes = ChangeSet::empty(data, Pending { all: true, ..Default::default() });
+        for (guild_id, ledger) in &data.guilds {
+            let mut settings = ledger.clone();
+            changes.ledgers.insert(*guild_id, std::mem::take(&mut settings.users).into_values().collect());
+            changes.settings.insert(*guild_id, settings);
+        }
+        return changes;
+    }
+
+    ///Copies what's pending and clears it
+    pub fn take(data: &mut Data) -> ChangeSet {
+        let pending = std::mem::take(&mut data.pending);
+        data.changes = 0;
+        if pending.all {
+            return ChangeSet::all(data);
+        }
+        let mut changes = ChangeSet::empty(data, pending.clone());
+        for guild_id in &pending.settings {
+            if let Some(ledger) = data.guilds.get_mut(guild_id) {
+                changes.settings.insert(*guild_id, ledger.settings());
+            }
+        }
+        for guild_id in &pending.ledgers {
+            let users = data.guild(*guild_id).map(|ledger| ledger.users.values().cloned().collect()).unwrap_or_default();
+            changes.ledgers.insert(*guild_id, users);
+        }
+        for (guild_id, user_id) in pending.users.iter().filter(|(guild_id, _)| !pending.ledgers.contains(guild_id)) {
+            let user = data.guild(*guild_id).and_then(|ledger| ledger.users.get(user_id)).cloned();
+            changes.users.insert((*guild_id, *user_id), user);
+        }
+        return changes;
+    }
+
+    fn empty(data: &Data, pending: Pending) -> ChangeSet {
+        return ChangeSet {
+            version: data.version,
+            last_timestamp: data.last_timestamp,
+            unassigned: data.unassigned.clone(),
+            complete: pending.all,
+            settings: HashMap::new(),
+            ledgers: HashMap::new(),
+            users: HashMap::new(),
+            pending
+        };
+    }
+
+    ///Makes the same changes to a copy of the data
+    pub fn apply(&self, data: &mut Data) {
+        data.version = self.version;
+        data.last_timestamp = self.last_timestamp;
+        data.unassigned = self.unassigned.clone();
+        if self.complete {
+            data.guilds.clear();
+        }
+        for (guild_id, settings) in &self.settings {
+            let users = data.guilds.remove(guild_id).map(|ledger| ledger.users).unwrap_or_default();
+            data.guilds.insert(*guild_id, GuildData { users, ..settings.clone() });
+        }
+        for (guild_id, users) in &self.ledgers {
+            data.guild_mut(*guild_id).users = users.iter().map(|user| (user.id, user.clone())).collect();
+        }
+        for ((guild_id, user_id), user) in &self.users {
+            let ledger = data.guild_mut(*guild_id);
+            match user {
+                Some(user) => ledger.users.insert(*user_id, user.clone()),
+                None => ledger.users.remove(user_id)
+            };
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    ///Nothing has been saved yet, this is a first run
+    Missing(String),
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Sqlite(rusqlite::Error),
+    Migration(MigrationError)
+}
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Missing(what) => write!(f, "{} doesn't exist", what),
+            StorageError::Io(why) => write!(f, "error reading file: {}", why),
+            StorageError::Parse(why) => write!(f, "error parsing json: {}", why),
+            StorageError::Sqlite(why) => write!(f, "sqlite error: {}", why),
+            StorageError::Migration(why) => write!(f, "{}", why)
+        }
+    }
+}
+impl From<rusqlite::Error> for StorageError {
+    fn from(why: rusqlite::Error) -> StorageError {
+        return StorageError::Sqlite(why);
+    }
+}
+impl From<serde_json::Error> for StorageError {
+    fn from(why: serde_json::Error) -> StorageError {
+        return StorageError::Parse(why);
+    }
+}
+
+///Picks the storage backend from STORAGE, either `json` (the default) or `sqlite`
+pub fn storage_from_env() -> Result<Arc<dyn Storage>, StorageError> {
+    return match env::var("STORAGE").unwrap_or("json".to_string()).as_str() {
+        "sqlite" => Ok(Arc::new(SqliteStorage::from_env()?)),
+        "json" => Ok(Arc::new(JsonStorage::from_env())),
+        other => Err(StorageError::Io(io::Error::other(format!("unknown STORAGE {:?}, expected json or sqlite", other))))
+    };
+}
+
+///Copies stats.txt (or the newest good backup of it) into a sqlite database
+pub fn import_json(json: &JsonStorage, sqlite: &SqliteStorage, ranks: &RankTables) -> Result<(), StorageError> {
+    let mut data = json.load()?;
+    data.assign_unassigned_from_env(ranks);
+    let users: usize = data.guilds.values().map(|ledger| ledger.users.len()).sum();
+    sqlite.save(&ChangeSet::all(&data))?;
+    println!("imported {} users in {} guilds", users, data.guilds.len());
+    return Ok(());
+}
+
+///Writes dirty data to storage in the background, so messages don't each cause a full rewrite
+pub struct Saver {
+    pub data: Arc<RwLock<Data>>,
+    pub storage: Arc<dyn Storage>,
+    ///Longest time dirty data waits before being saved
+    pub interval: Duration,
+    ///Save straight away once this many changes have piled up
+    pub max_changes: u64,
+    saving: Mutex<()>
+}
+impl Saver {
+    ///Reads SAVE_INTERVAL (seconds) and SAVE_AFTER_CHANGES, falling back to defaults
+    pub fn from_env(data: Arc<RwLock<Data>>, storage: Arc<dyn Storage>) -> Saver {
+        return Saver {
+            data,
+            storage,
+            interval: Duration::from_secs(env::var("SAVE_INTERVAL").ok().and_then(|secs| secs.parse().ok()).unwrap_or(30)),
+            max_changes: env::var("SAVE_AFTER_CHANGES").ok().and_then(|count| count.parse().ok()).unwrap_or(50),
+            saving: Mutex::new(())
+        };
+    }
+
+    ///Saves the data if it changed since the last save. Returns whether everything is saved.
+    pub async fn flush(&self) -> bool {
+        let _saving = self.saving.lock().await;
+        let changes = {
+            let mut data = self.data.write().await;
+            if !data.changed() {
+                return true;
+            }
+            ChangeSet::take(&mut data)
+        };
+        let pending = changes.pending.clone();
+        let storage = self.storage.clone();
+        let error = match tokio::task::spawn_blocking(move || storage.save(&changes)).await {
+            Ok(Ok(())) => {
+                println!("saved");
+                return true;
+            },
+            Ok(Err(why)) => why.to_string(),
+            Err(why) => why.to_string()
+        };
+        println!("save failed - {}", error);
+        // Keep it dirty so the next tick tries again
+        let mut data = self.data.write().await;
+        data.pending.merge(pending);
+        data.changes += 1;
+        return false;
+    }
+
+    ///Checks every second whether the data is due to be saved, never returns
+    pub async fn run(self: Arc<Self>) {
+        let mut last_save = Instant::now();
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            ticker.tick().await;
+            let (changed, changes) = {
+                let data = self.data.read().await;
+                (data.changed(), data.changes)
+            };
+            if changed && (changes >= self.max_changes || last_save.elapsed() >= self.interval) {
+                self.flush().await;
+                last_save = Instant::now();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub fn data() -> Data {
+        let at = Timestamp::from_unix_timestamp(0).unwrap();
+        let mut first = GuildData::default();
+        first.users.insert(1, UserData::new(1, "one".to_string(), at));
+        first.users.insert(2, UserData::new(2, "two".to_string(), at));
+        let mut second = GuildData::default();
+        second.users.insert(3, UserData::new(3, "three".to_string(), at));
+        return Data::from_data(HashMap::from([(10, first), (20, second)]), HashMap::new(), at);
+    }
+
+    #[test]
+    fn change_sets_only_carry_what_changed() {
+        let mut data = data();
+        let mut saved = data.clone();
+        data.guild_mut(10).users.get_mut(&1).unwrap().xp = 99;
+        data.mark_user_changed(10, 1);
+        data.guild_mut(20).users.remove(&3);
+        data.mark_user_changed(20, 3);
+        data.guild_mut(20).prefix = Some("?".to_string());
+        data.mark_guild_changed(20);
+
+        let changes = ChangeSet::take(&mut data);
+        assert!(!data.changed());
+        assert!(!changes.complete);
+        assert!(changes.ledgers.is_empty());
+        assert_eq!(changes.settings.keys().copied().collect::<Vec<u64>>(), vec![20]);
+        assert!(changes.settings[&20].users.is_empty());
+        assert_eq!(changes.users.len(), 2);
+        assert_eq!(changes.users[&(20, 3)], None);
+
+        changes.apply(&mut saved);
+        assert_eq!(saved.guilds, data.guilds);
+    }
+
+    #[test]
+    fn ledgers_replace_every_user_of_a_guild() {
+        let mut data = data();
+        let mut saved = data.clone();
+        data.guild_mut(10).users.remove(&2);
+        data.mark_user_changed(10, 1);
+        data.mark_ledger_changed(10);
+        let changes = ChangeSet::take(&mut data);
+        assert!(changes.users.is_empty());
+        assert_eq!(changes.ledgers[&10].len(), 1);
+        changes.apply(&mut saved);
+        assert_eq!(saved.guilds, data.guilds);
+    }
+}
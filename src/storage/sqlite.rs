@@ -0,0 +1,166 @@
+use std::{env, path::PathBuf, sync::Mutex};
+
+use rusqlite::{Connection, Transaction, params};
+use serde_json::{Map, Value, json};
+
+use crate::data::*;
+use crate::storage::{ChangeSet, Storage, StorageError};
+
+///Keeps users in an embedded sqlite database, one row per user per guild, and only writes the rows that changed.
+///The `data` columns hold the full serialized records, the others are copies kept for ad-hoc queries.
+pub struct SqliteStorage {
+    pub path: PathBuf,
+    conn: Mutex<Connection>
+}
+
+impl SqliteStorage {
+    ///Opens SQLITE_FILE, or stats.db by default
+    pub fn from_env() -> Result<SqliteStorage, StorageError> {
+        return SqliteStorage::open(PathBuf::from(env::var("SQLITE_FILE").unwrap_or("stats.db".to_string())));
+    }
+
+    ///Opens the database, creating the tables if they don't exist yet
+    pub fn open(path: PathBuf) -> Result<SqliteStorage, StorageError> {
+        let conn = Connection::open(&path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch("
+            CREATE TABLE IF NOT EXISTS meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS guilds (
+                guild_id INTEGER PRIMARY KEY,
+                data TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS users (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                xp INTEGER NOT NULL,
+                level INTEGER NOT NULL,
+                last_message_timestamp INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (guild_id, user_id)
+            );
+            CREATE INDEX IF NOT EXISTS users_by_xp ON users (guild_id, xp DESC);
+        ")?;
+        return Ok(SqliteStorage { path, conn: Mutex::new(conn) });
+    }
+}
+
+///Writes a guild's settings row, everything in `GuildData` but the users
+fn put_settings(tx: &Transaction, guild_id: u64, settings: &GuildData) -> Result<(), StorageError> {
+    let mut value = serde_json::to_value(settings)?;
+    if let Some(object) = value.as_object_mut() {
+        object.remove("users");
+    }
+    tx.execute("INSERT OR REPLACE INTO guilds (guild_id, data) VALUES (?1, ?2)", params![guild_id as i64, value.to_string()])?;
+    return Ok(());
+}
+
+fn put_user(tx: &Transaction, guild_id: u64, user: &UserData) -> Result<(), StorageError> {
+    tx.execute(
+        "INSERT OR REPLACE INTO users (guild_id, user_id, name, xp, level, last_message_timestamp, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![guild_id as i64, user.id as i64, user.name, user.xp as i64, user.level as i64, user.last_message_timestamp.unix_timestamp(), serde_json::to_string(user)?]
+    )?;
+    return Ok(());
+}
+
+impl Storage for SqliteStorage {
+    ///Puts the rows back together into a stats document, so it goes through the same migrations as stats.txt
+    fn load(&self) -> Result<Data, StorageError> {
+        let conn = self.conn.lock().expect("sqlite lock poisoned");
+        let mut document = Map::new();
+        {
+            let mut statement = conn.prepare("SELECT key, value FROM meta")?;
+            let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            for row in rows {
+                let (key, value) = row?;
+                document.insert(key, serde_json::from_str(&value)?);
+            }
+        }
+        if !document.contains_key("last_timestamp") {
+            return Err(StorageError::Missing(self.path.display().to_string()));
+        }
+
+        let mut guilds = Map::new();
+        {
+            let mut statement = conn.prepare("SELECT guild_id, data FROM guilds")?;
+            let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, String>(1)?)))?;
+            for row in rows {
+                let (guild_id, settings) = row?;
+                let mut value: Value = serde_json::from_str(&settings)?;
+                value["users"] = Value::Object(Map::new());
+                guilds.insert(guild_id.to_string(), value);
+            }
+        }
+        {
+            let mut statement = conn.prepare("SELECT guild_id, user_id, data FROM users")?;
+            let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64, row.get::<_, String>(2)?)))?;
+            for row in rows {
+                let (guild_id, user_id, user) = row?;
+                let guild = guilds.entry(guild_id.to_string()).or_insert(json!({"users": {}}));
+                guild["users"][user_id.to_string()] = serde_json::from_str(&user)?;
+            }
+        }
+        document.insert("guilds".to_string(), Value::Object(guilds));
+        // Rows from an older version come back marked as changed, so the first save rewrites them
+        let data = Data::from_document(Value::Object(document))?;
+        println!("loaded data from {}", self.path.display());
+        return Ok(data);
+    }
+
+    fn save(&self, changes: &ChangeSet) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().expect("sqlite lock poisoned");
+        let tx = conn.transaction()?;
+        tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES ('version', ?1)", params![changes.version.to_string()])?;
+        tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES ('last_timestamp', ?1)", params![serde_json::to_string(&changes.last_timestamp)?])?;
+        tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES ('unassigned', ?1)", params![serde_json::to_string(&changes.unassigned)?])?;
+        if changes.complete {
+            tx.execute_batch("DELETE FROM guilds; DELETE FROM users;")?;
+        }
+        for (guild_id, settings) in &changes.settings {
+            put_settings(&tx, *guild_id, settings)?;
+        }
+        for (guild_id, users) in &changes.ledgers {
+            tx.execute("DELETE FROM users WHERE guild_id = ?1", params![*guild_id as i64])?;
+            for user in users {
+                put_user(&tx, *guild_id, user)?;
+            }
+        }
+        for ((guild_id, user_id), user) in &changes.users {
+            match user {
+                Some(user) => put_user(&tx, *guild_id, user)?,
+                None => {
+                    tx.execute("DELETE FROM users WHERE guild_id = ?1 AND user_id = ?2", params![*guild_id as i64, *user_id as i64])?;
+                }
+            }
+        }
+        tx.commit()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::data;
+
+    #[test]
+    fn saves_changes_and_loads_them_back() {
+        let storage = SqliteStorage::open(PathBuf::from(":memory:")).unwrap();
+        let mut data = data();
+        storage.save(&ChangeSet::all(&data)).unwrap();
+        data.guild_mut(10).users.get_mut(&1).unwrap().xp = 99;
+        data.mark_user_changed(10, 1);
+        data.guild_mut(10).users.remove(&2);
+        data.mark_user_changed(10, 2);
+        data.guild_mut(20).prefix = Some("?".to_string());
+        data.mark_guild_changed(20);
+        storage.save(&ChangeSet::take(&mut data)).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.guilds, data.guilds);
+        assert!(!loaded.changed());
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+
+use serenity::all::{ChannelId, RoleId, UserId};
+
+///The prefix used in servers that haven't set their own
+pub const DEFAULT_PREFIX: &str = "!";
+
+///One piece of a command's arguments
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    ///A bare word, or everything between a pair of quotes
+    Text(String),
+    ///A bare non-negative number, which can also stand for an id
+    Number(u64),
+    ///`<@id>` or `<@!id>`
+    User(UserId),
+    ///`<@&id>`
+    Role(RoleId),
+    ///`<#id>`
+    Channel(ChannelId),
+    ///`--name`, the value (if any) is the next token
+    Flag(String)
+}
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Text(text) => write!(f, "{}", text),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::User(user_id) => write!(f, "<@{}>", user_id),
+            Token::Role(role_id) => write!(f, "<@&{}>", role_id),
+            Token::Channel(channel_id) => write!(f, "<#{}>", channel_id),
+            Token::Flag(name) => write!(f, "--{}", name)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TokenizeError {
+    ///A quote was opened at this character and never closed
+    UnterminatedQuote(usize)
+}
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenizeError::UnterminatedQuote(at) => write!(f, "the quote at character {} is never closed", at + 1)
+        }
+    }
+}
+
+///A message split into the command name and its argument tokens
+#[derive(Debug, PartialEq)]
+pub struct CommandLine {
+    pub name: String,
+    pub tokens: Result<Vec<Token>, TokenizeError>
+}
+
+///Splits a message into a command if it starts with the prefix. The name is lowercased.
+pub fn parse_command_line(content: &str, prefix: &str) -> Option<CommandLine> {
+    let rest = content.strip_prefix(prefix)?;
+    let name_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let name = &rest[..name_end];
+    if name.is_empty() {
+        return None;
+    }
+    return Some(CommandLine { name: name.to_lowercase(), tokens: tokenize(&rest[name_end..]) });
+}
+
+///Splits arguments on whitespace, keeping quoted strings together and recognising mentions, numbers and flags.
+///Quotes can be `"` or `“”`, and a backslash inside them escapes the next character.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, TokenizeError> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().enumerate().peekable();
+    while let Some((at, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if let Some(close) = closing_quote(c) {
+            let mut text = String::new();
+            let mut closed = false;
+            while let Some((_, c)) = chars.next() {
+                if c == close {
+                    closed = true;
+                    break;
+                }
+                if c == '\\' {
+                    if let Some((_, escaped)) = chars.next() {
+                        text.push(escaped);
+                    }
+                    continue;
+                }
+                text.push(c);
+            }
+            if !closed {
+                return Err(TokenizeError::UnterminatedQuote(at));
+            }
+            tokens.push(Token::Text(text));
+            continue;
+        }
+        let mut word = c.to_string();
+        while let Some((_, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            word.push(*c);
+            chars.next();
+        }
+        // `--name=value` is the same as `--name value`
+        if let Some((name, value)) = word.strip_prefix("--").and_then(|flag| flag.split_once('=')) {
+            if !name.is_empty() {
+                tokens.push(Token::Flag(name.to_lowercase()));
+                tokens.push(classify(value.to_string()));
+                continue;
+            }
+        }
+        tokens.push(classify(word));
+    }
+    return Ok(tokens);
+}
+
+///The quote that ends a quoted string starting with `c`, if `c` opens one
+fn closing_quote(c: char) -> Option<char> {
+    return match c {
+        '"' => Some('"'),
+        '“' => Some('”'),
+        _ => None
+    };
+}
+
+fn classify(word: String) -> Token {
+    if let Some(inner) = word.strip_prefix('<').and_then(|rest| rest.strip_suffix('>')) {
+        if let Some(id) = inner.strip_prefix("@&").and_then(parse_id) {
+            return Token::Role(RoleId::new(id));
+        }
+        if let Some(id) = inner.strip_prefix("@!").or(inner.strip_prefix('@')).and_then(parse_id) {
+            return Token::User(UserId::new(id));
+        }
+        if let Some(id) = inner.strip_prefix('#').and_then(parse_id) {
+            return Token::Channel(ChannelId::new(id));
+        }
+    }
+    if let Some(name) = word.strip_prefix("--") {
+        if !name.is_empty() {
+            return Token::Flag(name.to_lowercase());
+        }
+    }
+    if let Ok(number) = word.parse::<u64>() {
+        return Token::Number(number);
+    }
+    return Token::Text(word);
+}
+
+///Ids are never 0, so that's treated as not an id
+fn parse_id(id: &str) -> Option<u64> {
+    return id.parse::<u64>().ok().filter(|id| *id != 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Token {
+        return Token::Text(text.to_string());
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(tokenize("  some   name\there ").unwrap(), vec![text("some"), text("name"), text("here")]);
+        assert_eq!(tokenize("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn keeps_quoted_strings_together() {
+        assert_eq!(tokenize(r#""Some Name" it's “smart quotes”"#).unwrap(), vec![text("Some Name"), text("it's"), text("smart quotes")]);
+        assert_eq!(tokenize(r#""say \"hi\" \\ now""#).unwrap(), vec![text(r#"say "hi" \ now"#)]);
+        assert_eq!(tokenize(r#""" x"#).unwrap(), vec![text(""), text("x")]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quotes() {
+        assert_eq!(tokenize(r#"ok "never closed"#), Err(TokenizeError::UnterminatedQuote(3)));
+    }
+
+    #[test]
+    fn recognises_mentions() {
+        assert_eq!(tokenize("<@123> <@!456> <@&789> <#42>").unwrap(), vec![
+            Token::User(UserId::new(123)),
+            Token::User(UserId::new(456)),
+            Token::Role(RoleId::new(789)),
+            Token::Channel(ChannelId::new(42))
+        ]);
+        assert_eq!(tokenize("<@> <@abc> <@0> <:emoji:5>").unwrap(), vec![text("<@>"), text("<@abc>"), text("<@0>"), text("<:emoji:5>")]);
+    }
+
+    #[test]
+    fn recognises_numbers() {
+        assert_eq!(tokenize("3 123456789012345678 -4 1.5").unwrap(), vec![Token::Number(3), Token::Number(123456789012345678), text("-4"), text("1.5")]);
+    }
+
+    #[test]
+    fn recognises_flags() {
+        assert_eq!(tokenize("--Sort xp --page=2 -- -x").unwrap(), vec![Token::Flag("sort".to_string()), text("xp"), Token::Flag("page".to_string()), Token::Number(2), text("--"), text("-x")]);
+        assert_eq!(tokenize(r#""--not a flag""#).unwrap(), vec![text("--not a flag")]);
+    }
+
+    #[test]
+    fn parses_command_lines_with_any_prefix() {
+        assert_eq!(parse_command_line("!XP <@1>", "!"), Some(CommandLine { name: "xp".to_string(), tokens: Ok(vec![Token::User(UserId::new(1))]) }));
+        assert_eq!(parse_command_line("lb?leaderboard 2", "lb?"), Some(CommandLine { name: "leaderboard".to_string(), tokens: Ok(vec![Token::Number(2)]) }));
+        assert_eq!(parse_command_line("!help", "?"), None);
+        assert_eq!(parse_command_line("! help", "!"), None);
+        assert_eq!(parse_command_line("!", "!"), None);
+        assert!(parse_command_line("!xp \"oops", "!").unwrap().tokens.is_err());
+    }
+}
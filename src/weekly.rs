@@ -0,0 +1,122 @@
+use std::{sync::Arc, time::Duration};
+
+use serenity::all::{ChannelId, CreateEmbed, CreateEmbedFooter, CreateMessage, Http, Timestamp};
+use serenity::prelude::*;
+
+use crate::data::*;
+
+///How often to check whether a week has ended
+const CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+///A finished week's top members, ready to post
+#[derive(Debug, PartialEq)]
+pub struct Announcement {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub week: i64,
+    ///Names and the XP they earned that week, most first
+    pub top: Vec<(u64, String, u64)>
+}
+impl Announcement {
+    pub fn embed(&self) -> CreateEmbed {
+        let mut description = format!("For the week starting <t:{}:D>\n\n", week_start(self.week) * 86400);
+        if self.top.is_empty() {
+            description += "Nobody earned any XP";
+        }
+        for (index, (id, _, xp)) in self.top.iter().enumerate() {
+            description += &format!("**#{}** <@{}> with {} xp\n", index + 1, id, xp);
+        }
+        return CreateEmbed::new().title("Top members this week").description(description)
+            .footer(CreateEmbedFooter::new("Set up with the weeklytop command"));
+    }
+}
+
+///Works out which guilds are owed an announcement for the week that just ended.
+///Only the last finished week gets announced, weeks missed while the bot was down are skipped.
+pub fn due_announcements(data: &Data, now: Timestamp) -> Vec<Announcement> {
+    let current = week_number(day_number(now));
+    let mut announcements = vec![];
+    for (guild_id, ledger) in &data.guilds {
+        let Some(settings) = &ledger.weekly_top else {
+            continue;
+        };
+        if settings.next_week >= current {
+            continue;
+        }
+        let week = current - 1;
+        let first = week_start(week);
+        let top = ledger.ranked_by(|user| user.xp_between(first, first + 6)).into_iter()
+            .filter(|(_, xp)| *xp > 0)
+            .take(settings.count)
+            .map(|(user, xp)| (user.id, user.name.clone(), xp))
+            .collect();
+        announcements.push(Announcement { guild_id: *guild_id, channel_id: settings.channel_id, week, top });
+    }
+    return announcements;
+}
+
+///Records that a guild's announcement went out, so the week isn't announced again
+pub fn mark_announced(data: &mut Data, announcement: &Announcement) {
+    if let Some(settings) = data.guilds.get_mut(&announcement.guild_id).and_then(|ledger| ledger.weekly_top.as_mut()) {
+        settings.next_week = settings.next_week.max(announcement.week + 1);
+        data.mark_guild_changed(announcement.guild_id);
+    }
+}
+
+///Posts each guild's weekly top members once their week is over. A week only counts as announced once
+///the post went through, failed ones are tried again on the next check.
+pub async fn run(http: Arc<Http>, data: Arc<RwLock<Data>>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let announcements = due_announcements(&*data.read().await, Timestamp::now());
+        for announcement in announcements {
+            match ChannelId::new(announcement.channel_id).send_message(&http, CreateMessage::new().embed(announcement.embed())).await {
+                Ok(_) => mark_announced(&mut *data.write().await, &announcement),
+                Err(why) => println!("Error sending weekly announcement: {why:?}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn at_day(day: i64) -> Timestamp {
+        return Timestamp::from_unix_timestamp(day * 86400 + 3600).unwrap();
+    }
+
+    #[test]
+    fn announces_each_finished_week_once() {
+        let monday = week_start(2900);
+        let mut ledger = GuildData { weekly_top: Some(WeeklyTopSettings { channel_id: 7, count: 2, next_week: 2900 }), ..Default::default() };
+        for (id, xp_by_day) in [(1, vec![(monday, 5)]), (2, vec![(monday + 6, 9)]), (3, vec![(monday + 2, 1)]), (4, vec![(monday - 1, 50), (monday + 7, 50)])] {
+            let mut user = UserData::new(id, format!("user {}", id), at_day(monday));
+            for (day, xp) in xp_by_day {
+                user.add_xp(xp, at_day(day));
+            }
+            ledger.users.insert(id, user);
+        }
+        let mut data = Data::from_data(HashMap::from([(1, ledger)]), HashMap::new(), at_day(monday));
+
+        assert_eq!(due_announcements(&data, at_day(monday + 6)), vec![]);
+        let announced = due_announcements(&data, at_day(monday + 7));
+        assert_eq!(announced, vec![Announcement { guild_id: 1, channel_id: 7, week: 2900, top: vec![(2, "user 2".to_string(), 9), (1, "user 1".to_string(), 5)] }]);
+        // Until the post goes through the week is still owed
+        assert_eq!(due_announcements(&data, at_day(monday + 8)), announced);
+        assert!(!data.changed());
+        mark_announced(&mut data, &announced[0]);
+        assert!(data.changed());
+        assert_eq!(due_announcements(&data, at_day(monday + 8)), vec![]);
+        assert_eq!(data.guild(1).unwrap().weekly_top.as_ref().unwrap().next_week, 2901);
+    }
+
+    #[test]
+    fn guilds_without_settings_are_left_alone() {
+        let data = Data::from_data(HashMap::from([(1, GuildData::default())]), HashMap::new(), at_day(0));
+        assert_eq!(due_announcements(&data, at_day(100)), vec![]);
+        assert!(!data.changed());
+    }
+}
@@ -0,0 +1,414 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+use serenity::all::{GuildChannel, Message, MessageType, Timestamp};
+
+use crate::data::{GuildData, UserData};
+use crate::ranks::RankTable;
+
+///Largest multiplier a channel or role can have
+pub const MAX_MULTIPLIER: f64 = 10.0;
+///Most XP a message can be worth before multipliers, attachment bonus included
+pub const MAX_MESSAGE_XP: u64 = 1000;
+
+///How a guild hands out XP. The defaults are the original rule, 1 XP per message at most once a minute.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct XpPolicy {
+    ///Seconds that have to pass after earning XP before the next message earns more
+    pub cooldown: i64,
+    ///Each award is a random amount from `min_xp` to `max_xp`, both included
+    pub min_xp: u64,
+    pub max_xp: u64,
+    ///Messages shorter than this many characters earn nothing, unless they have an attachment
+    pub min_length: usize,
+    ///Extra XP for a message with at least one attachment
+    pub attachment_bonus: u64,
+    ///Multipliers by channel or category id, a channel's own multiplier wins over its category's
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub channel_multipliers: HashMap<u64, f64>,
+    ///Multipliers by role id, the highest one a member has applies
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub role_multipliers: HashMap<u64, f64>,
+    ///Channels and categories where messages never earn XP, like spam or counting channels
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub no_xp_channels: BTreeSet<u64>,
+    ///Roles whose members never earn XP, like a muted role
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub no_xp_roles: BTreeSet<u64>
+}
+impl Default for XpPolicy {
+    fn default() -> XpPolicy {
+        return XpPolicy { cooldown: 60, min_xp: 1, max_xp: 1, min_length: 0, attachment_bonus: 0, channel_multipliers: HashMap::new(), role_multipliers: HashMap::new(), no_xp_channels: BTreeSet::new(), no_xp_roles: BTreeSet::new() };
+    }
+}
+impl XpPolicy {
+    pub fn is_default(&self) -> bool {
+        return *self == XpPolicy::default();
+    }
+
+    ///Checks the settings make sense, the error is shown to whoever changed them
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cooldown < 0 {
+            return Err("The cooldown can't be negative".to_string());
+        }
+        if self.min_xp > self.max_xp {
+            return Err(format!("The minimum XP ({}) is above the maximum ({})", self.min_xp, self.max_xp));
+        }
+        if self.max_xp.saturating_add(self.attachment_bonus) > MAX_MESSAGE_XP {
+            return Err(format!("A message can be worth at most {} XP, the maximum plus the attachment bonus is {}", MAX_MESSAGE_XP, self.max_xp.saturating_add(self.attachment_bonus)));
+        }
+        if let Some(multiplier) = self.channel_multipliers.values().chain(self.role_multipliers.values()).find(|multiplier| !(0.0..=MAX_MULTIPLIER).contains(*multiplier)) {
+            return Err(format!("Multipliers have to be between 0 and {}, not {}", MAX_MULTIPLIER, multiplier));
+        }
+        return Ok(());
+    }
+
+    ///Whether a message was sent somewhere, or by someone, that never earns XP. It still counts as a message.
+    pub fn excludes(&self, message: &XpMessage) -> bool {
+        return self.no_xp_channels.contains(&message.channel_id)
+            || message.category_id.is_some_and(|category_id| self.no_xp_channels.contains(&category_id))
+            || message.role_ids.iter().any(|role_id| self.no_xp_roles.contains(role_id));
+    }
+
+    ///Whether a message can earn XP, `last` is when the user last earned any
+    pub fn earns(&self, last: Option<Timestamp>, message: &XpMessage) -> bool {
+        if self.excludes(message) {
+            return false;
+        }
+        if message.length < self.min_length && message.attachments == 0 {
+            return false;
+        }
+        return match last {
+            Some(last) => self.cooldown_left(last, message.timestamp) == 0,
+            None => true
+        };
+    }
+
+    ///Seconds until someone who last earned XP at `last` can earn more, 0 once they can
+    pub fn cooldown_left(&self, last: Timestamp, now: Timestamp) -> i64 {
+        if self.cooldown == 0 {
+            return 0;
+        }
+        return (self.cooldown + 1 - (now.unix_timestamp() - last.unix_timestamp())).max(0);
+    }
+
+    ///How much XP a message is worth. The random part is drawn from the message id, so replaying a message gives the same amount.
+    pub fn amount(&self, message: &XpMessage) -> u64 {
+        let span = self.max_xp - self.min_xp + 1;
+        let mut xp = self.min_xp + mix(message.id) % span;
+        if message.attachments > 0 {
+            xp += self.attachment_bonus;
+        }
+        let channel = self.channel_multipliers.get(&message.channel_id)
+            .or(message.category_id.and_then(|category_id| self.channel_multipliers.get(&category_id)))
+            .copied().unwrap_or(1.0);
+        let role = message.role_ids.iter().filter_map(|role_id| self.role_multipliers.get(role_id)).copied().fold(None, |best: Option<f64>, multiplier| Some(best.map_or(multiplier, |best| best.max(multiplier)))).unwrap_or(1.0);
+        return (xp as f64 * channel * role).round() as u64;
+    }
+}
+
+///Whether a message was written by a person. Bots (the bot itself included), webhooks and system messages
+///like joins, pins and boosts are never recorded.
+pub fn from_person(message: &Message) -> bool {
+    return !message.author.bot && !message.author.system && message.webhook_id.is_none()
+        && matches!(message.kind, MessageType::Regular | MessageType::InlineReply);
+}
+
+///What XP rules need to know about a message, taken from either a live message or one fetched during catch-up
+#[derive(Clone, Debug, PartialEq)]
+pub struct XpMessage {
+    pub id: u64,
+    pub timestamp: Timestamp,
+    ///Length of the text in characters
+    pub length: usize,
+    pub attachments: usize,
+    pub channel_id: u64,
+    pub category_id: Option<u64>,
+    ///Roles the author has
+    pub role_ids: Vec<u64>
+}
+impl XpMessage {
+    pub fn new(message: &Message, channel: Option<&GuildChannel>, role_ids: Vec<u64>) -> XpMessage {
+        return XpMessage {
+            id: message.id.into(),
+            timestamp: message.timestamp,
+            length: message.content.chars().count(),
+            attachments: message.attachments.len(),
+            channel_id: message.channel_id.into(),
+            category_id: channel.and_then(|channel| channel.parent_id).map(u64::from),
+            role_ids
+        };
+    }
+}
+
+///What handling one message did to its author
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Award {
+    ///The message put the author on the ledger
+    pub new: bool,
+    ///XP awarded, 0 when the message didn't earn any
+    pub xp: u64,
+    ///The author's XP now belongs to a different level than the one they hold
+    pub level_changed: bool
+}
+impl Award {
+    ///Whether the author's rank role and level need to be brought up to date on Discord
+    pub fn needs_level_update(&self) -> bool {
+        return self.new || self.level_changed;
+    }
+}
+
+///Handles one message from `user_id` in a guild. This is the only place messages turn into XP, both live
+///messages and ones fetched when catching up after downtime go through here, so the two always agree.
+///The message's timestamp and context (channel, roles, length) are in `message`. Levels of existing users
+///are left for `update_level`, which needs the old level to announce rank ups.
+pub fn award_xp(ledger: &mut GuildData, user_id: u64, name: &str, message: &XpMessage, ranks: &RankTable) -> Award {
+    // No XP is handed out between seasons, messages are still counted
+    let off_season = ledger.off_season;
+    let new = !ledger.users.contains_key(&user_id);
+    let user = ledger.users.entry(user_id).or_insert_with(|| UserData::new(user_id, name.to_string(), message.timestamp));
+    user.name = name.to_string();
+    let xp = if off_season {
+        if !new {
+            user.messages += 1;
+        }
+        0
+    } else {
+        award_message(&ledger.xp_policy, user, new, message)
+    };
+    if new {
+        user.level = ranks.level(user.xp);
+    }
+    return Award { new, xp, level_changed: ranks.level(user.xp) != user.level };
+}
+
+///Puts messages fetched from several channels in the order they would have arrived live, by time and then by
+///id since snowflakes only grow
+pub fn sort_for_replay<T>(items: &mut [T], message: impl Fn(&T) -> &XpMessage) {
+    items.sort_by_key(|item| {
+        let message = message(item);
+        return (message.timestamp, message.id);
+    });
+}
+
+///Counts a message towards a user and awards XP if the policy allows it. `new` is for the message that
+///put the user on the ledger, which always gets past the cooldown. Returns the XP awarded.
+fn award_message(policy: &XpPolicy, user: &mut UserData, new: bool, message: &XpMessage) -> u64 {
+    if !new {
+        user.messages += 1;
+    }
+    if !policy.earns(if new {None} else {Some(user.last_message_timestamp)}, message) {
+        return 0;
+    }
+    let xp = policy.amount(message);
+    user.last_message_timestamp = message.timestamp;
+    user.add_xp(xp, message.timestamp);
+    return xp;
+}
+
+///splitmix64, a small fixed mixing function so amounts never change between builds or dependency versions
+fn mix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::announcements::AnnouncementSettings;
+    use crate::data::update_level;
+
+    fn message(id: u64, at: i64) -> XpMessage {
+        return XpMessage { id, timestamp: Timestamp::from_unix_timestamp(at).unwrap(), length: 20, attachments: 0, channel_id: 1, category_id: Some(2), role_ids: vec![] };
+    }
+
+    #[test]
+    fn default_policy_is_one_xp_a_minute() {
+        let policy = XpPolicy::default();
+        let mut user = UserData::new(9, "user".to_string(), Timestamp::from_unix_timestamp(0).unwrap());
+        assert_eq!(award_message(&policy, &mut user, true, &message(1, 0)), 1);
+        assert_eq!(award_message(&policy, &mut user, false, &message(2, 60)), 0);
+        assert_eq!(award_message(&policy, &mut user, false, &message(3, 61)), 1);
+        assert_eq!(award_message(&policy, &mut user, false, &message(4, 100)), 0);
+        assert_eq!((user.xp, user.messages, user.last_message_timestamp.unix_timestamp()), (2, 4, 61));
+    }
+
+    #[test]
+    fn random_amounts_are_in_range_and_repeatable() {
+        let policy = XpPolicy { min_xp: 15, max_xp: 25, ..Default::default() };
+        let amounts: Vec<u64> = (0..500).map(|id| policy.amount(&message(id, 0))).collect();
+        assert!(amounts.iter().all(|xp| (15..=25).contains(xp)));
+        assert!(amounts.contains(&15) && amounts.contains(&25));
+        assert_eq!(amounts, (0..500).map(|id| policy.amount(&message(id, 0))).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn short_messages_need_an_attachment() {
+        let policy = XpPolicy { min_length: 30, attachment_bonus: 2, ..Default::default() };
+        assert!(!policy.earns(None, &message(1, 0)));
+        let with_image = XpMessage { attachments: 1, ..message(1, 0) };
+        assert!(policy.earns(None, &with_image));
+        assert_eq!(policy.amount(&with_image), 3);
+    }
+
+    #[test]
+    fn multipliers_stack_channel_and_best_role() {
+        let policy = XpPolicy {
+            min_xp: 10,
+            max_xp: 10,
+            channel_multipliers: HashMap::from([(2, 2.0), (5, 0.5)]),
+            role_multipliers: HashMap::from([(7, 1.5), (8, 1.2)]),
+            ..Default::default()
+        };
+        assert_eq!(policy.amount(&message(1, 0)), 20);
+        assert_eq!(policy.amount(&XpMessage { channel_id: 5, ..message(1, 0) }), 5);
+        assert_eq!(policy.amount(&XpMessage { role_ids: vec![8, 7], ..message(1, 0) }), 30);
+        assert_eq!(policy.amount(&XpMessage { category_id: None, role_ids: vec![8], ..message(1, 0) }), 12);
+    }
+
+    #[test]
+    fn zero_cooldown_awards_every_message() {
+        let policy = XpPolicy { cooldown: 0, ..Default::default() };
+        assert!(policy.earns(Some(Timestamp::from_unix_timestamp(5).unwrap()), &message(1, 5)));
+    }
+
+    #[test]
+    fn cooldown_left_matches_what_earns() {
+        let policy = XpPolicy { cooldown: 30, ..Default::default() };
+        let last = Timestamp::from_unix_timestamp(100).unwrap();
+        for (now, left) in [(100, 31), (120, 11), (130, 1), (131, 0), (500, 0)] {
+            assert_eq!(policy.cooldown_left(last, Timestamp::from_unix_timestamp(now).unwrap()), left);
+            assert_eq!(policy.earns(Some(last), &message(1, now)), left == 0);
+        }
+    }
+
+    #[test]
+    fn excluded_channels_categories_and_roles_earn_nothing() {
+        let policy = XpPolicy { no_xp_channels: BTreeSet::from([5, 2]), no_xp_roles: BTreeSet::from([7]), ..Default::default() };
+        assert!(!policy.earns(None, &XpMessage { category_id: None, channel_id: 5, ..message(1, 0) }));
+        assert!(!policy.earns(None, &message(1, 0)));
+        assert!(!policy.earns(None, &XpMessage { category_id: None, role_ids: vec![8, 7], ..message(1, 0) }));
+        assert!(policy.earns(None, &XpMessage { category_id: None, role_ids: vec![8], ..message(1, 0) }));
+        let mut user = UserData::new(9, "user".to_string(), Timestamp::from_unix_timestamp(0).unwrap());
+        assert_eq!(award_message(&policy, &mut user, false, &message(2, 100)), 0);
+        assert_eq!((user.xp, user.messages), (0, 2));
+    }
+
+    #[test]
+    fn only_people_are_recorded() {
+        let mut message = Message::default();
+        assert!(from_person(&message));
+        message.kind = MessageType::InlineReply;
+        assert!(from_person(&message));
+        message.author.bot = true;
+        assert!(!from_person(&message));
+        message.author.bot = false;
+        message.webhook_id = Some(5.into());
+        assert!(!from_person(&message));
+        message.webhook_id = None;
+        message.kind = MessageType::MemberJoin;
+        assert!(!from_person(&message));
+    }
+
+    #[test]
+    fn rejects_nonsense_settings() {
+        assert!(XpPolicy::default().validate().is_ok());
+        assert!(XpPolicy { min_xp: 5, max_xp: 1, ..Default::default() }.validate().is_err());
+        assert!(XpPolicy { cooldown: -1, ..Default::default() }.validate().is_err());
+        assert!(XpPolicy { max_xp: MAX_MESSAGE_XP, ..Default::default() }.validate().is_ok());
+        assert!(XpPolicy { max_xp: MAX_MESSAGE_XP, attachment_bonus: 1, ..Default::default() }.validate().is_err());
+        assert!(XpPolicy { min_xp: i64::MAX as u64, max_xp: i64::MAX as u64, attachment_bonus: i64::MAX as u64, ..Default::default() }.validate().is_err());
+        assert!(XpPolicy { role_multipliers: HashMap::from([(1, 11.0)]), ..Default::default() }.validate().is_err());
+    }
+
+    ///Brings the level up to date the way the handler does, without telling Discord
+    fn settle(ledger: &mut GuildData, user_id: u64, award: Award, ranks: &RankTable) {
+        if award.needs_level_update() {
+            let user = ledger.users.get_mut(&user_id).unwrap();
+            update_level(user, ranks, &AnnouncementSettings::default());
+        }
+    }
+
+    fn receive(ledger: &mut GuildData, messages: &[(u64, XpMessage)], ranks: &RankTable) -> u64 {
+        let mut total = 0;
+        for (user_id, message) in messages {
+            let award = award_xp(ledger, *user_id, &format!("user {}", user_id), message, ranks);
+            settle(ledger, *user_id, award, ranks);
+            total += award.xp;
+        }
+        return total;
+    }
+
+    ///Collects messages the way catching up does, channel by channel and newest first, then sorts them
+    fn replay_order(messages: &[(u64, XpMessage)]) -> Vec<(u64, XpMessage)> {
+        let mut fetched: Vec<(u64, XpMessage)> = vec![];
+        for channel_id in [3, 1, 2] {
+            fetched.extend(messages.iter().rev().filter(|(_, message)| message.channel_id == channel_id).cloned());
+        }
+        sort_for_replay(&mut fetched, |(_, message)| message);
+        return fetched;
+    }
+
+    fn policies() -> impl Strategy<Value = XpPolicy> {
+        let multiplier = prop::sample::select(vec![0.0, 0.5, 1.5, 2.0]);
+        let excluded = (prop::collection::btree_set(1..4u64, 0..2), prop::collection::btree_set(10..13u64, 0..2));
+        return (0..120i64, 1..5u64, 0..10u64, 0..20usize, 0..3u64, prop::collection::hash_map(1..4u64, multiplier.clone(), 0..3), prop::collection::hash_map(10..13u64, multiplier, 0..3), excluded)
+            .prop_map(|(cooldown, min_xp, spread, min_length, attachment_bonus, channel_multipliers, role_multipliers, (no_xp_channels, no_xp_roles))| XpPolicy {
+                cooldown,
+                min_xp,
+                max_xp: min_xp + spread,
+                min_length,
+                attachment_bonus,
+                channel_multipliers,
+                role_multipliers,
+                no_xp_channels,
+                no_xp_roles
+            });
+    }
+
+    ///Message histories in the order they arrive live, ids grow with time like snowflakes do
+    fn histories() -> impl Strategy<Value = Vec<(u64, XpMessage)>> {
+        let message = (1..6u64, 0..30i64, 0..40usize, 0..2usize, 1..4u64, prop::collection::vec(10..13u64, 0..3));
+        return prop::collection::vec(message, 0..150).prop_map(|messages| {
+            let mut at = 1_700_000_000;
+            return messages.into_iter().enumerate().map(|(index, (user_id, gap, length, attachments, channel_id, role_ids))| {
+                at += gap;
+                let message = XpMessage { id: index as u64 + 1, timestamp: Timestamp::from_unix_timestamp(at).unwrap(), length, attachments, channel_id, category_id: Some(9), role_ids };
+                return (user_id, message);
+            }).collect();
+        });
+    }
+
+    proptest! {
+        #[test]
+        fn replaying_a_history_matches_receiving_it_live(policy in policies(), history in histories(), split in 0..150usize, off_season: bool) {
+            let ranks = RankTable::default();
+            let start = GuildData { xp_policy: policy, off_season, ..Default::default() };
+            let mut live = start.clone();
+            receive(&mut live, &history, &ranks);
+
+            // The bot was up for the first part and caught up on the rest after restarting
+            let split = split.min(history.len());
+            let mut replayed = start;
+            receive(&mut replayed, &history[..split], &ranks);
+            receive(&mut replayed, &replay_order(&history[split..]), &ranks);
+            prop_assert_eq!(live, replayed);
+        }
+
+        #[test]
+        fn every_message_is_counted_and_xp_adds_up(policy in policies(), history in histories()) {
+            let ranks = RankTable::default();
+            let mut ledger = GuildData { xp_policy: policy, ..Default::default() };
+            let total = receive(&mut ledger, &history, &ranks);
+            prop_assert_eq!(ledger.users.values().map(|user| user.xp).sum::<u64>(), total);
+            for user in ledger.users.values() {
+                prop_assert_eq!(user.messages, history.iter().filter(|(user_id, _)| *user_id == user.id).count() as u64);
+                prop_assert_eq!(user.level, ranks.level(user.xp));
+            }
+        }
+    }
+}